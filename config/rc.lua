@@ -52,6 +52,9 @@ rlens.gallery_height_width_ratio(1)
 
 rlens.status_bar_position('bottom')
 
+-- Uncomment this to reload images when their files change
+--rlens.watch(true)
+
 
 -- Scaling / align
 
@@ -86,12 +89,37 @@ function next_scaling_mode()
 end
 
 
+-- History
+
+-- The image this image list was left at, offered until resumed
+local resume = rlens.history()
+if resume ~= nil and resume.index == nil then
+    resume = nil
+end
+
+
 -- Status bar
 
 function query.status_bar()
     local image = rlens.current_image()
 
     local left = '[' .. rlens.index() .. '/' .. rlens.total_images() .. '] ' .. image.filestem
+    local collections = rlens.total_collections()
+    if collections > 1 then
+        left = '{' .. rlens.collection() .. '/' .. collections .. '} ' .. left
+    end
+    if image.marked then
+        left = left .. ' *'
+    end
+
+    local marked = #rlens.marked()
+    if marked > 0 then
+        left = left .. ' (' .. marked .. ' marked)'
+    end
+
+    if resume ~= nil and resume.index ~= rlens.index() then
+        left = left .. ' (u: resume)'
+    end
 
     local right = ''
     local metadata = image.metadata
@@ -111,6 +139,29 @@ bind('q', rlens.exit)
 bind('f', rlens.toggle_fullscreen)
 bind('d', rlens.toggle_status_bar)
 bind('S-r', rlens.reload)
+bind('m', function()
+    rlens.toggle_mark()
+    rlens.refresh_status_bar()
+end)
+bind('C-Tab', function()
+    rlens.switch_collection(rlens.collection() % rlens.total_collections() + 1)
+end)
+bind('S-C-Tab', function()
+    local total = rlens.total_collections()
+    rlens.switch_collection((rlens.collection() - 2) % total + 1)
+end)
+bind('C-w', function()
+    if rlens.total_collections() > 1 then
+        rlens.close_collection()
+    end
+end)
+bind('u', function()
+    if resume ~= nil then
+        rlens.goto(resume.index)
+        resume = nil
+        rlens.refresh_status_bar()
+    end
+end)
 
 -- Mode changing
 bind_image('Tab', function() rlens.mode('gallery') end)
@@ -183,3 +234,7 @@ function hook.resize()
     rlens.reset()
 end
 
+function hook.file_change(change)
+    rlens.refresh_status_bar()
+end
+
@@ -0,0 +1,138 @@
+//! Module for color management of decoded images
+//!
+//! Images with an embedded ICC profile are converted from that profile into a target profile.
+//! Full images target the display profile (sRGB by default), while thumbnails always target sRGB
+//! so that the thumbnail cache is independent of the display.
+
+use image::{DynamicImage, RgbaImage};
+use qcms::{DataType, Intent, Profile, Transform};
+use std::fs;
+use std::path::Path;
+
+/// The profiles that images are converted into
+#[derive(Debug)]
+pub struct TargetProfiles {
+    /// The profile of the display, for full images
+    pub display: Box<Profile>,
+    /// The sRGB profile, for thumbnails
+    pub srgb: Box<Profile>,
+}
+
+/// The color space of an ICC profile
+#[derive(PartialEq)]
+enum ColorSpace {
+    Rgb,
+    Gray,
+    /// A color space that we cannot convert from
+    Other,
+}
+
+impl TargetProfiles {
+    /// Create the target profiles
+    /// The display profile is read from `display_profile` if provided, and otherwise is sRGB
+    pub fn new(display_profile: Option<&Path>) -> Result<Self, String> {
+        let display = display_profile
+            .map(read_display_profile)
+            .transpose()?
+            .unwrap_or_else(Profile::new_sRGB);
+
+        Ok(Self {
+            display,
+            srgb: Profile::new_sRGB(),
+        })
+    }
+}
+
+/// Read the display profile from an ICC file
+fn read_display_profile(path: &Path) -> Result<Box<Profile>, String> {
+    let data = fs::read(path).map_err(|e| {
+        format!(
+            "Failed to read display profile at `{}`: {}",
+            path.display(),
+            e
+        )
+    })?;
+
+    if color_space(&data) != ColorSpace::Rgb {
+        return Err(format!(
+            "Error: The display profile at `{}` is not an RGB profile",
+            path.display()
+        ));
+    }
+
+    let mut profile = Profile::new_from_slice(&data, false)
+        .ok_or_else(|| format!("Error: Invalid display profile at `{}`", path.display()))?;
+    profile.precache_output_transform();
+
+    Ok(profile)
+}
+
+/// A conversion from an image's embedded profile into a target profile
+pub struct ColorTransform {
+    transform: Transform,
+    /// Whether the source profile is grayscale
+    gray: bool,
+}
+
+impl ColorTransform {
+    /// Create the transform from the ICC profile `icc` into the `target` profile
+    /// Returns `None` if the conversion would have no effect
+    pub fn new(icc: &[u8], target: &Profile) -> Result<Option<Self>, String> {
+        let invalid = || "Invalid embedded color profile".to_string();
+
+        let gray = match color_space(icc) {
+            ColorSpace::Rgb => false,
+            ColorSpace::Gray => true,
+            ColorSpace::Other => {
+                return Err("Unsupported color space in the embedded color profile".to_string())
+            }
+        };
+
+        let input = Profile::new_from_slice(icc, false).ok_or_else(invalid)?;
+
+        // Skip the conversion when it would have no effect
+        if input.is_sRGB() && target.is_sRGB() {
+            return Ok(None);
+        }
+
+        let input_type = if gray {
+            DataType::GrayA8
+        } else {
+            DataType::RGBA8
+        };
+        let transform = Transform::new_to(
+            &input,
+            target,
+            input_type,
+            DataType::RGBA8,
+            Intent::default(),
+        )
+        .ok_or_else(invalid)?;
+
+        Ok(Some(Self { transform, gray }))
+    }
+
+    /// Convert an image
+    /// The converted image is in RGBA8
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        if self.gray {
+            let gray = image.into_luma_alpha8();
+            let mut rgba = RgbaImage::new(gray.width(), gray.height());
+            self.transform.convert(&gray, &mut rgba);
+            DynamicImage::ImageRgba8(rgba)
+        } else {
+            let mut rgba = image.into_rgba8();
+            self.transform.apply(&mut rgba);
+            DynamicImage::ImageRgba8(rgba)
+        }
+    }
+}
+
+/// Read the color space from the header of an ICC profile
+fn color_space(icc: &[u8]) -> ColorSpace {
+    match icc.get(16..20) {
+        Some(b"RGB ") => ColorSpace::Rgb,
+        Some(b"GRAY") => ColorSpace::Gray,
+        _ => ColorSpace::Other,
+    }
+}
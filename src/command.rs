@@ -1,16 +1,25 @@
 //! Module for rlens' internal command API.
 
-use crate::command_types::{Color, ImageDetails, TransformDetails};
+use crate::command_types::{Color, HistoryDetails, ImageDetails, TagsDetails, TransformDetails};
+use crate::file_ops;
 use crate::hooks::Hooks;
+use crate::image::{ImageKey, ImageSource};
+use crate::image_loader;
 use crate::image_transform;
 use crate::lua::{LuaContext, LuaResult};
+use crate::playlist;
 use crate::program::{Program, Request, RequestSender};
 use crate::rlens;
+use crate::session::Session;
+use crate::sidecar::MAX_RATING;
 use crate::status_bar;
-use crate::util::StrError;
+use crate::tone_map;
+use crate::util::{absolute_path, StrError};
 
+use std::collections::HashSet;
 use std::error::Error;
 use std::fmt::{self, Debug, Display};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{sync_channel, SyncSender};
 
 /// Run a command from the lua thread
@@ -112,6 +121,28 @@ pub enum CommandError {
     NonPositive(f32),
     /// Zoom factor 0
     ZoomZero,
+    /// Failure to watch files
+    Watch(String),
+    /// Change that would leave the image list empty
+    EmptyList,
+    /// Filter that no image passes
+    NoMatch,
+    /// Rating outside of the valid range
+    Rating(u8),
+    /// Failure to read or write a sidecar
+    Sidecar(String),
+    /// Failure to read or write a session
+    Session(String),
+    /// Failure to write a playlist
+    Playlist(String),
+    /// Collection index out of range
+    CollectionIndex(usize),
+    /// Closing the only collection
+    LastCollection,
+    /// Failure to trash, move, copy or rename a file
+    File(String),
+    /// Failure to save the orientation of an image
+    Orientation(String),
 }
 
 /// Command error display
@@ -123,6 +154,17 @@ impl Display for CommandError {
             ImageIndex(i) => format!("Image index `{}` was out of range", i),
             NonPositive(x) => format!("Expected a positive value, got `{}`", x),
             ZoomZero => "Cannot set zoom to 0".to_string(),
+            Watch(e) => e.clone(),
+            EmptyList => "The image list cannot be empty".to_string(),
+            NoMatch => "No images pass the filter".to_string(),
+            Rating(r) => format!("Rating `{}` is out of range (max: {})", r, MAX_RATING),
+            Sidecar(e) => e.clone(),
+            Session(e) => e.clone(),
+            Playlist(e) => e.clone(),
+            CollectionIndex(i) => format!("Collection index `{}` was out of range", i),
+            LastCollection => "The only collection cannot be closed".to_string(),
+            File(e) => e.clone(),
+            Orientation(e) => e.clone(),
         };
 
         write!(f, "{}", error_msg)
@@ -188,6 +230,48 @@ fn gallery_cursor_move(p: &mut Program, hooks: &mut Hooks) {
     }
 }
 
+/// Replace the image list with `replace`, keeping the watched files in sync
+fn replace_image_list(p: &mut Program, replace: impl FnOnce(&mut Program)) {
+    p.rlens.clear_filter(&mut p.gfx);
+    change_image_list(p, replace);
+}
+
+/// Change the image list with `change`, such as to switch collections, keeping the watched
+/// files in sync
+fn change_image_list<T>(p: &mut Program, change: impl FnOnce(&mut Program) -> T) -> T {
+    let previous = p
+        .rlens
+        .images()
+        .iter()
+        .map(|image| image.path().to_path_buf())
+        .collect::<Vec<_>>();
+
+    let output = change(p);
+
+    for path in previous {
+        if p.rlens.image_indices(&path).is_empty() {
+            p.watcher.remove(&path);
+        }
+    }
+    for image in p.rlens.images() {
+        p.watcher.add(image.path());
+    }
+
+    p.wake_image_loader();
+    redraw(p);
+    output
+}
+
+/// Update the tone mapping of high precision images
+fn update_tone_mapping(update: impl FnOnce(&mut tone_map::ToneMapping), p: &mut Program) {
+    let mut tone_mapping = p.rlens.tone_mapping();
+    update(&mut tone_mapping);
+
+    if p.rlens.set_tone_mapping(tone_mapping, &mut p.gfx) {
+        redraw_image_view(p);
+    }
+}
+
 /// Update the image transform
 fn update_transform(
     update: impl FnOnce(&mut image_transform::ImageTransform),
@@ -865,6 +949,64 @@ impl Command for Reload {
     }
 }
 
+/// Load the image at the position regardless of the decoding limits
+#[derive(Debug)]
+pub struct IgnoreLimits(pub usize);
+
+impl Command for IgnoreLimits {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_position(self.0, p)?;
+        p.rlens.ignore_limits(index, &mut p.gfx);
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Set whether the files of the image list are watched for changes
+#[derive(Debug)]
+pub struct Watch(pub bool);
+
+impl Command for Watch {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        if self.0 {
+            let paths = p
+                .rlens
+                .images()
+                .iter()
+                .filter_map(|image| image.source().file_path());
+            p.watcher.enable(paths).map_err(CommandError::Watch)?;
+        } else {
+            p.watcher.disable();
+        }
+        Ok(())
+    }
+}
+
+/// Set whether watched directories add new images to the image list and drop deleted ones
+#[derive(Debug)]
+pub struct WatchDirectories(pub bool);
+
+impl Command for WatchDirectories {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.watcher.set_track_directories(self.0);
+        Ok(())
+    }
+}
+
+/// Set whether images are oriented by their EXIF orientation
+#[derive(Debug)]
+pub struct AutoOrient(pub bool);
+
+impl Command for AutoOrient {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let view = p.window_size();
+        p.rlens.set_auto_orient(self.0, view);
+        redraw(p);
+        Ok(())
+    }
+}
+
 /// Set the preloading range for full images
 #[derive(Debug)]
 pub struct PreloadRange(pub usize, pub usize);
@@ -887,6 +1029,759 @@ impl Command for SaveThumbnails {
     }
 }
 
+/// Add an image to the image list at a position (default: the end)
+#[derive(Debug)]
+pub struct AddImage(pub PathBuf, pub Option<usize>);
+
+impl Command for AddImage {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let total = p.rlens.total_images();
+        let index = match self.1 {
+            Some(pos) if pos < 1 || pos > total + 1 => return Err(CommandError::ImageIndex(pos)),
+            Some(pos) => pos - 1,
+            None => total,
+        };
+
+        p.rlens
+            .insert_image(index, ImageSource::File(self.0.clone()), &mut p.gfx);
+        p.watcher.add(&self.0);
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Remove the image at an index from the image list
+/// The last image cannot be removed
+#[derive(Debug)]
+pub struct RemoveImage(pub usize);
+
+impl Command for RemoveImage {
+    fn run(&self, p: &mut Program, hooks: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_position(self.0, p)?;
+        if p.rlens.total_images() == 1 {
+            return Err(CommandError::EmptyList);
+        }
+
+        let path = p.rlens.images()[index].path().to_path_buf();
+        if p.rlens.remove_image(index, &mut p.gfx) {
+            hooks.current_image_change();
+        }
+        if p.rlens.image_indices(&path).is_empty() {
+            p.watcher.remove(&path);
+        }
+
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Replace the image list
+#[derive(Debug)]
+pub struct SetImageList(pub Vec<PathBuf>);
+
+impl Command for SetImageList {
+    fn run(&self, p: &mut Program, hooks: &mut Hooks, _: ()) -> CommandResult<()> {
+        if self.0.is_empty() {
+            return Err(CommandError::EmptyList);
+        }
+
+        replace_image_list(p, |p| {
+            let sources = self.0.iter().cloned().map(ImageSource::File).collect();
+            if p.rlens.set_image_list(sources, &mut p.gfx) {
+                hooks.current_image_change();
+            }
+        });
+        Ok(())
+    }
+}
+
+/// Move the files of the image at an index to the trash, and remove it from the image list
+/// If only some members of its stack are trashed, they are removed from the stack
+/// The last image cannot be trashed
+#[derive(Debug)]
+pub struct TrashImage(pub usize);
+
+impl Command for TrashImage {
+    fn run(&self, p: &mut Program, hooks: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_position(self.0, p)?;
+        if p.rlens.total_images() == 1 {
+            return Err(CommandError::EmptyList);
+        }
+
+        let image = p.rlens.get_image(index);
+        let paths = image
+            .stack_members()
+            .map(|source| {
+                source.file_path().map(absolute_path).ok_or_else(|| {
+                    let path = source.path().display();
+                    CommandError::File(format!("Image `{}` has no file to trash", path))
+                })
+            })
+            .collect::<CommandResult<Vec<_>>>()?;
+
+        let mut trashed = Vec::new();
+        let mut result = Ok(());
+        for path in &paths {
+            if let Err(e) = file_ops::trash_file(path) {
+                result = Err(CommandError::File(e));
+                break;
+            }
+            image_loader::remove_cached(path, &p.thumbnail_dir);
+            trashed.push(path);
+        }
+
+        if trashed.len() == paths.len() {
+            change_image_list(p, |p| {
+                if p.rlens.remove_image(index, &mut p.gfx) {
+                    hooks.current_image_change();
+                }
+            });
+        } else if !trashed.is_empty() {
+            let is_trashed = |source: &ImageSource| {
+                source
+                    .file_path()
+                    .is_some_and(|path| trashed.contains(&&absolute_path(path)))
+            };
+            change_image_list(p, |p| {
+                if p.rlens.remove_stack_members(index, is_trashed, &mut p.gfx)
+                    && index == p.rlens.current_open_image()
+                {
+                    hooks.current_image_change();
+                }
+            });
+        }
+        result
+    }
+}
+
+/// Move the files of the image at an index into a directory
+#[derive(Debug)]
+pub struct MoveImage(pub usize, pub PathBuf);
+
+impl Command for MoveImage {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_position(self.0, p)?;
+        relocate_image(index, p, |path| file_ops::path_in_dir(path, &self.1))
+    }
+}
+
+/// Rename the files of the image at an index
+/// The members of a stack take the stem of the name, and keep their extensions
+#[derive(Debug)]
+pub struct RenameImage(pub usize, pub String);
+
+impl Command for RenameImage {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_position(self.0, p)?;
+        let stem = p.rlens.get_image(index).stack_size() > 1;
+        relocate_image(index, p, |path| file_ops::renamed_path(path, &self.1, stem))
+    }
+}
+
+/// Move the files of the image at an index to the paths from `destination`, along with their
+/// cached thumbnails
+fn relocate_image(
+    index: usize,
+    p: &mut Program,
+    destination: impl Fn(&Path) -> Result<PathBuf, String>,
+) -> CommandResult<()> {
+    change_image_list(p, |p| {
+        let thumbnail_dir = &p.thumbnail_dir;
+        p.rlens.relocate_image(index, |path| {
+            let to = destination(path)?;
+            let from = absolute_path(path);
+            file_ops::move_file(path, &to)?;
+            image_loader::move_cached(&from, &absolute_path(&to), thumbnail_dir);
+            Ok(to)
+        })
+    })
+    .map_err(CommandError::File)
+}
+
+/// Copy the files of the image at an index into a directory
+/// The image list is unchanged
+#[derive(Debug)]
+pub struct CopyImage(pub usize, pub PathBuf);
+
+impl Command for CopyImage {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_position(self.0, p)?;
+
+        for source in p.rlens.get_image(index).stack_members() {
+            let path = source.file_path().ok_or_else(|| {
+                let path = source.path().display();
+                CommandError::File(format!("Image `{}` has no file to copy", path))
+            })?;
+            let to = file_ops::path_in_dir(path, &self.1).map_err(CommandError::File)?;
+            file_ops::copy_file(path, &to).map_err(CommandError::File)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Save the rotation and flip of the current open image to its file
+/// The rotation must be a multiple of 90 degrees
+#[derive(Debug)]
+pub struct SaveOrientation;
+
+impl Command for SaveOrientation {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let path = p
+            .rlens
+            .save_orientation(&mut p.gfx)
+            .map_err(CommandError::Orientation)?;
+        image_loader::remove_cached(&absolute_path(&path), &p.thumbnail_dir);
+
+        p.wake_image_loader();
+        Ok(())
+    }
+}
+
+/// Add a collection of images after the other collections
+/// Outputs the index of the new collection
+#[derive(Debug)]
+pub struct NewCollection(pub Vec<PathBuf>);
+
+impl Command for NewCollection {
+    type Output = usize;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<usize> {
+        if self.0.is_empty() {
+            return Err(CommandError::EmptyList);
+        }
+
+        let sources = self.0.iter().cloned().map(ImageSource::File).collect();
+        Ok(p.rlens.add_collection(sources) + 1)
+    }
+}
+
+/// Get the index of the active collection
+#[derive(Debug)]
+pub struct ActiveCollection;
+
+impl Command for ActiveCollection {
+    type Output = usize;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<usize> {
+        Ok(p.rlens.active_collection() + 1)
+    }
+}
+
+/// Get the number of collections
+#[derive(Debug)]
+pub struct TotalCollections;
+
+impl Command for TotalCollections {
+    type Output = usize;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<usize> {
+        Ok(p.rlens.total_collections())
+    }
+}
+
+/// Make the collection at an index active
+#[derive(Debug)]
+pub struct SwitchCollection(pub usize);
+
+impl Command for SwitchCollection {
+    fn run(&self, p: &mut Program, hooks: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_collection(self.0, p)?;
+        if index == p.rlens.active_collection() {
+            return Ok(());
+        }
+
+        change_image_list(p, |p| p.rlens.switch_collection(index, &mut p.gfx));
+        hooks.current_image_change();
+        hooks.transform_update();
+        Ok(())
+    }
+}
+
+/// Close the collection at an index (default: the active collection)
+#[derive(Debug)]
+pub struct CloseCollection(pub Option<usize>);
+
+impl Command for CloseCollection {
+    fn run(&self, p: &mut Program, hooks: &mut Hooks, _: ()) -> CommandResult<()> {
+        let active = p.rlens.active_collection();
+        let index = match self.0 {
+            Some(pos) => validate_collection(pos, p)?,
+            None => active,
+        };
+        if p.rlens.total_collections() == 1 {
+            return Err(CommandError::LastCollection);
+        }
+
+        if index == active {
+            change_image_list(p, |p| p.rlens.close_collection(index, &mut p.gfx));
+            hooks.current_image_change();
+            hooks.transform_update();
+        } else {
+            p.rlens.close_collection(index, &mut p.gfx);
+        }
+        Ok(())
+    }
+}
+
+/// Convert a position to a valid collection index
+fn validate_collection(pos: usize, p: &Program) -> CommandResult<usize> {
+    if pos < 1 || pos > p.rlens.total_collections() {
+        return Err(CommandError::CollectionIndex(pos));
+    }
+
+    Ok(pos - 1)
+}
+
+/// Save the state of rlens as a session file
+#[derive(Debug)]
+pub struct SaveSession(pub PathBuf);
+
+impl Command for SaveSession {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens
+            .session()
+            .write(&self.0)
+            .map_err(CommandError::Session)
+    }
+}
+
+/// Restore the state of rlens from a session file
+#[derive(Debug)]
+pub struct LoadSession(pub PathBuf);
+
+impl Command for LoadSession {
+    fn run(&self, p: &mut Program, hooks: &mut Hooks, _: ()) -> CommandResult<()> {
+        let session = Session::read(&self.0).map_err(CommandError::Session)?;
+
+        replace_image_list(p, |p| p.rlens.restore_session(&session, &mut p.gfx));
+        hooks.current_image_change();
+        hooks.transform_update();
+        Ok(())
+    }
+}
+
+/// Write the image list (or only the marked images) as a playlist
+#[derive(Debug)]
+pub struct ExportList(pub PathBuf, pub bool);
+
+impl Command for ExportList {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let result = if self.1 {
+            playlist::write(&self.0, p.rlens.marked_file_paths())
+        } else {
+            playlist::write(&self.0, p.rlens.file_paths())
+        };
+        result.map_err(CommandError::Playlist)
+    }
+}
+
+/// Get the last viewed image of the image list from a previous run
+#[derive(Debug)]
+pub struct History;
+
+impl Command for History {
+    type Output = Option<HistoryDetails>;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<Option<HistoryDetails>> {
+        let entry = match p.history.get(p.rlens.file_paths()) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let index = p
+            .rlens
+            .images()
+            .iter()
+            .position(|image| absolute_path(image.path()) == entry.path);
+        Ok(Some(HistoryDetails::new(index, entry.path, entry.time)))
+    }
+}
+
+/// Set whether the image at an index (default: the current image) is marked
+#[derive(Debug)]
+pub struct Mark(pub Option<usize>, pub bool);
+
+impl Command for Mark {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = mark_index(self.0, p)?;
+        p.rlens.set_marked(index, self.1);
+        redraw_gallery(p);
+        Ok(())
+    }
+}
+
+/// Toggle whether the image at an index (default: the current image) is marked
+#[derive(Debug)]
+pub struct ToggleMark(pub Option<usize>);
+
+impl Command for ToggleMark {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = mark_index(self.0, p)?;
+        let marked = p.rlens.images()[index].is_marked();
+        p.rlens.set_marked(index, !marked);
+        redraw_gallery(p);
+        Ok(())
+    }
+}
+
+/// Get the index of the image to mark from an optional position
+fn mark_index(pos: Option<usize>, p: &Program) -> CommandResult<usize> {
+    match pos {
+        Some(pos) => validate_position(pos, p),
+        None => Ok(p.rlens.current_image()),
+    }
+}
+
+/// Get the indices of the marked images
+#[derive(Debug)]
+pub struct Marked;
+
+impl Command for Marked {
+    type Output = Vec<usize>;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<Vec<usize>> {
+        Ok(p.rlens.marked().into_iter().map(|i| i + 1).collect())
+    }
+}
+
+/// Set the rating of the image at an index in its sidecar
+#[derive(Debug)]
+pub struct SetRating(pub usize, pub u8);
+
+impl Command for SetRating {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_position(self.0, p)?;
+        if self.1 > MAX_RATING {
+            return Err(CommandError::Rating(self.1));
+        }
+
+        p.rlens
+            .update_tags(index, |tags| tags.rating = self.1.into())
+            .map_err(CommandError::Sidecar)?;
+        redraw_gallery(p);
+        Ok(())
+    }
+}
+
+/// Get the tags of the image at an index
+#[derive(Debug)]
+pub struct Tags(pub usize);
+
+impl Command for Tags {
+    type Output = TagsDetails;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<TagsDetails> {
+        let index = validate_position(self.0, p)?;
+        let image = &p.rlens.images()[index];
+        Ok(match image.tags() {
+            Some(tags) => TagsDetails::Loaded(tags.tags.clone()),
+            None => TagsDetails::Unloaded(image.source().file_path().map(Path::to_path_buf)),
+        })
+    }
+}
+
+/// Set the tags of the image at an index in its sidecar
+#[derive(Debug)]
+pub struct SetTags(pub usize, pub Vec<String>);
+
+impl Command for SetTags {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = validate_position(self.0, p)?;
+        p.rlens
+            .update_tags(index, |tags| tags.tags = self.1.clone())
+            .map_err(CommandError::Sidecar)?;
+        Ok(())
+    }
+}
+
+/// Hide the images whose keys are not in a set
+#[derive(Debug)]
+pub struct Filter(pub HashSet<ImageKey>);
+
+impl Command for Filter {
+    fn run(&self, p: &mut Program, hooks: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.clear_filter(&mut p.gfx);
+        if !p
+            .rlens
+            .images()
+            .iter()
+            .any(|image| self.0.contains(&image.key()))
+        {
+            return Err(CommandError::NoMatch);
+        }
+
+        if p.rlens
+            .filter(|image| self.0.contains(&image.key()), &mut p.gfx)
+        {
+            hooks.current_image_change();
+        }
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Show the images hidden by the filter
+#[derive(Debug)]
+pub struct ClearFilter;
+
+impl Command for ClearFilter {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.clear_filter(&mut p.gfx);
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Get the index of an image in the image list without the filter
+#[derive(Debug)]
+pub struct AbsoluteIndex(pub usize);
+
+impl Command for AbsoluteIndex {
+    type Output = usize;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<usize> {
+        let index = validate_position(self.0, p)?;
+        Ok(p.rlens.absolute_index(index) + 1)
+    }
+}
+
+/// Sort the image list by a key
+#[derive(Debug)]
+pub struct Sort(pub rlens::SortKey, pub bool);
+
+impl Command for Sort {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.sort(self.0, self.1, &mut p.gfx);
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Get the keys and details of all images, in order
+#[derive(Debug)]
+pub struct AllImages;
+
+impl Command for AllImages {
+    type Output = Vec<(ImageKey, ImageDetails)>;
+
+    fn run(
+        &self,
+        p: &mut Program,
+        _: &mut Hooks,
+        _: (),
+    ) -> CommandResult<Vec<(ImageKey, ImageDetails)>> {
+        Ok(p.rlens
+            .images()
+            .iter()
+            .map(|image| (image.key(), ImageDetails::collect(image)))
+            .collect())
+    }
+}
+
+/// Reorder the image list to follow a list of image keys
+#[derive(Debug)]
+pub struct Reorder(pub Vec<ImageKey>);
+
+impl Command for Reorder {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.reorder(&self.0, &mut p.gfx);
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Group the images into stacks
+#[derive(Debug)]
+pub struct Stack(pub rlens::StackRule);
+
+impl Command for Stack {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.stack(|path| self.0.key(path), &mut p.gfx);
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Split all stacks into their members
+#[derive(Debug)]
+pub struct Unstack;
+
+impl Command for Unstack {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.unstack(&mut p.gfx);
+        p.wake_image_loader();
+        redraw(p);
+        Ok(())
+    }
+}
+
+/// Get the paths of all images, including the members of stacks
+#[derive(Debug)]
+pub struct MemberPaths;
+
+impl Command for MemberPaths {
+    type Output = Vec<PathBuf>;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<Vec<PathBuf>> {
+        Ok(p.rlens
+            .images()
+            .iter()
+            .flat_map(|image| image.stack_members())
+            .map(|source| source.path().to_path_buf())
+            .collect())
+    }
+}
+
+/// Get the paths of the members of the current image's stack, starting with the primary member
+#[derive(Debug)]
+pub struct StackMembers;
+
+impl Command for StackMembers {
+    type Output = Vec<String>;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<Vec<String>> {
+        let image = p.rlens.get_image(p.rlens.current_image());
+        Ok(image
+            .stack_members()
+            .map(|source| source.path().to_string_lossy().into_owned())
+            .collect())
+    }
+}
+
+/// Make the next member of the current image's stack its primary member
+#[derive(Debug)]
+pub struct StackNext;
+
+impl Command for StackNext {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        let index = p.rlens.current_image();
+        if p.rlens.stack_next(index, &mut p.gfx) {
+            p.wake_image_loader();
+            redraw(p);
+        }
+        Ok(())
+    }
+}
+
+/// Set whether the perceptual hashes of images are computed in the background
+#[derive(Debug)]
+pub struct HashImages(pub bool);
+
+impl Command for HashImages {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.set_hash_images(self.0);
+        p.wake_image_loader();
+        Ok(())
+    }
+}
+
+/// Get the positions of the images that are similar to the image at a position
+/// Images are similar if their hashes differ in at most `threshold` bits
+#[derive(Debug)]
+pub struct Similar(pub usize, pub u32);
+
+impl Command for Similar {
+    type Output = Vec<usize>;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<Vec<usize>> {
+        let index = validate_position(self.0, p)?;
+        let similar = p.rlens.similar_images(index, self.1);
+        Ok(similar.into_iter().map(|i| i + 1).collect())
+    }
+}
+
+/// Get the groups of positions of images that are duplicates of each other
+/// Images are duplicates if their hashes differ in at most `threshold` bits (default `0`)
+#[derive(Debug)]
+pub struct DuplicateGroups(pub Option<u32>);
+
+impl Command for DuplicateGroups {
+    type Output = Vec<Vec<usize>>;
+
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<Vec<Vec<usize>>> {
+        let groups = p.rlens.duplicate_groups(self.0.unwrap_or(0));
+        Ok(groups
+            .into_iter()
+            .map(|group| group.into_iter().map(|i| i + 1).collect())
+            .collect())
+    }
+}
+
+/// Set whether images are converted from their embedded color profiles
+/// Loaded full images are reloaded to apply the change
+#[derive(Debug)]
+pub struct ColorManagement(pub bool);
+
+impl Command for ColorManagement {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        if p.rlens.color_management() == self.0 {
+            return Ok(());
+        }
+
+        p.rlens.set_color_management(self.0);
+        p.rlens.refresh_full_images(&mut p.gfx);
+        p.wake_image_loader();
+        Ok(())
+    }
+}
+
+/// Toggle whether images are converted from their embedded color profiles
+#[derive(Debug)]
+pub struct ToggleColorManagement;
+
+impl Command for ToggleColorManagement {
+    fn run(&self, p: &mut Program, hooks: &mut Hooks, _: ()) -> CommandResult<()> {
+        let on = !p.rlens.color_management();
+        ColorManagement(on).run(p, hooks, ())
+    }
+}
+
+/// Set the exposure adjustment of high precision images in stops
+#[derive(Debug)]
+pub struct Exposure(pub f32);
+
+impl Command for Exposure {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        update_tone_mapping(|t| t.exposure = self.0, p);
+        Ok(())
+    }
+}
+
+/// Set the display gamma used to tone map high precision images
+/// `gamma` > 0
+#[derive(Debug)]
+pub struct Gamma(pub f32);
+
+impl Command for Gamma {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        if self.0 <= 0.0 {
+            return Err(CommandError::NonPositive(self.0));
+        }
+
+        update_tone_mapping(|t| t.gamma = self.0, p);
+        Ok(())
+    }
+}
+
+/// Set the tone mapping operator for high precision images
+#[derive(Debug)]
+pub struct ToneMap(pub tone_map::ToneMapOperator);
+
+impl Command for ToneMap {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        update_tone_mapping(|t| t.operator = self.0, p);
+        Ok(())
+    }
+}
+
 /// Set the gallery tile width
 /// `width` > 0
 #[derive(Debug)]
@@ -1085,6 +1980,54 @@ impl Command for GalleryBorderColor {
     }
 }
 
+/// Set the color of the tiles of images that failed to load in the gallery
+#[derive(Debug)]
+pub struct GalleryErrorColor(pub Color);
+
+impl Command for GalleryErrorColor {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.set_gallery_error_color(self.0 .0);
+        redraw_gallery(p);
+        Ok(())
+    }
+}
+
+/// Set the color of the rating pips on rated images in the gallery
+#[derive(Debug)]
+pub struct GalleryRatingColor(pub Color);
+
+impl Command for GalleryRatingColor {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.set_gallery_rating_color(self.0 .0);
+        redraw_gallery(p);
+        Ok(())
+    }
+}
+
+/// Set the color of the indicators on marked images in the gallery
+#[derive(Debug)]
+pub struct GalleryMarkColor(pub Color);
+
+impl Command for GalleryMarkColor {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.set_gallery_mark_color(self.0 .0);
+        redraw_gallery(p);
+        Ok(())
+    }
+}
+
+/// Set the background color of the badges on stacked images in the gallery
+#[derive(Debug)]
+pub struct GalleryBadgeColor(pub Color);
+
+impl Command for GalleryBadgeColor {
+    fn run(&self, p: &mut Program, _: &mut Hooks, _: ()) -> CommandResult<()> {
+        p.rlens.set_gallery_badge_color(self.0 .0);
+        redraw_gallery(p);
+        Ok(())
+    }
+}
+
 /// Set the background color of the status bar
 #[derive(Debug)]
 pub struct StatusBarColor(pub Color);
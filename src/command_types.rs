@@ -3,16 +3,22 @@
 //! The key role of this module is to implement `rlua::{FromLua, ToLua}` on the
 //! input / output types respectively
 
-use crate::image::{Image, Metadata};
+use crate::image::{
+    ExifDetails, FileDetails, GpsLocation, Image, ImageSource, LoadError, LoadErrorKind, Metadata,
+};
+use crate::image_loader::{extract_metadata, file_details};
 use crate::image_transform::{AlignX, AlignY, ImageTransform, Scaling};
-use crate::rlens::Mode;
+use crate::rlens::{Mode, SortKey};
+use crate::sidecar::{read_tags, ImageTags};
 use crate::status_bar::StatusBarPosition;
+use crate::tone_map::ToneMapOperator;
 use crate::util::StrError;
+use crate::watcher::FileChangeKind;
 
 use rlua::prelude::{LuaError, LuaResult};
 use rlua::{Context, FromLua, ToLua, Value};
 use std::ffi::{OsStr, OsString};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 impl FromLua<'_> for Mode {
@@ -49,28 +55,63 @@ impl ToLua<'_> for Mode {
 pub struct ImageDetails {
     /// The path of the image as provided to rlens
     path: PathBuf,
-    /// The absolute path of the image
-    absolute_path: Option<PathBuf>,
     /// The filename of the image
     filename: Option<OsString>,
     /// The stem of the filename
     filestem: Option<OsString>,
     /// Metadata of the image
     metadata: Option<Metadata>,
+    /// Details from the EXIF data of the image
+    exif: Option<ExifDetails>,
+    /// Details of the image's file
+    file: Option<FileDetails>,
+    /// The error from the last failed load
+    error: Option<LoadError>,
+    /// The number of members in the image's stack
+    stack_size: usize,
+    /// Whether the image is marked
+    marked: bool,
+    /// The star rating of the image
+    rating: u8,
+    /// The keyword tags of the image
+    tags: Vec<String>,
+    /// The source of an image whose metadata is not loaded
+    /// The remaining details are read from it when converting to lua
+    unloaded: Option<ImageSource>,
+}
+
+/// The keyword tags of an image
+#[derive(Debug)]
+pub enum TagsDetails {
+    /// The tags from the loaded metadata of the image
+    Loaded(Vec<String>),
+    /// The path of the image whose sidecar the tags are read from when converting to lua
+    Unloaded(Option<PathBuf>),
 }
 
 impl ToLua<'_> for ImageDetails {
-    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+    fn to_lua(mut self, ctx: Context) -> LuaResult<Value> {
+        // This runs on the lua thread, so the details that need the disk are read here to keep
+        // them off the main thread
+        if let Some(source) = self.unloaded.take() {
+            self.read_unloaded(&source);
+        }
+        let absolute_path = self.path.canonicalize().ok();
+
         let t = ctx.create_table()?;
 
         t.set("path", pathbuf_to_string(self.path))?;
-        t.set(
-            "absolute_path",
-            self.absolute_path.and_then(pathbuf_to_string),
-        )?;
+        t.set("absolute_path", absolute_path.and_then(pathbuf_to_string))?;
         t.set("filename", self.filename.and_then(os_string_to_string))?;
         t.set("filestem", self.filestem.and_then(os_string_to_string))?;
         t.set("metadata", self.metadata)?;
+        t.set("exif", self.exif)?;
+        t.set("file", self.file)?;
+        t.set("error", self.error)?;
+        t.set("stack_size", self.stack_size)?;
+        t.set("marked", self.marked)?;
+        t.set("rating", self.rating)?;
+        t.set("tags", self.tags)?;
 
         Ok(Value::Table(t))
     }
@@ -78,28 +119,153 @@ impl ToLua<'_> for ImageDetails {
 
 impl ImageDetails {
     /// Collect relevant details from an `Image`
+    /// This does not touch the disk, as it runs on the main thread
     pub fn collect(image: &Image) -> Self {
-        Self {
+        let mut details = Self {
             path: image.path().to_path_buf(),
-            absolute_path: image.path().canonicalize().ok(),
             filename: image.path().file_name().map(OsStr::to_os_string),
             filestem: image.path().file_stem().map(OsStr::to_os_string),
-            metadata: image.metadata.loaded().cloned(),
+            metadata: None,
+            exif: None,
+            file: None,
+            error: image.error().cloned(),
+            stack_size: image.stack_size(),
+            marked: image.is_marked(),
+            rating: 0,
+            tags: Vec::new(),
+            unloaded: None,
+        };
+
+        match image.metadata.loaded() {
+            Some(metadata) => details.set_metadata(metadata.clone()),
+            None => details.unloaded = Some(image.source().clone()),
         }
+
+        details
+    }
+
+    /// Read the details of an image whose metadata is not loaded from its source
+    fn read_unloaded(&mut self, source: &ImageSource) {
+        match extract_metadata(source) {
+            Ok(metadata) => self.set_metadata(metadata),
+            // Images that only the external loaders can decode still have their file and sidecar
+            Err(_) => {
+                self.file = source.file_path().and_then(file_details);
+                self.set_tags(read_sidecar(source.file_path()));
+            }
+        }
+    }
+
+    fn set_metadata(&mut self, metadata: Metadata) {
+        self.exif = metadata.exif.clone();
+        self.file = metadata.file.clone();
+        self.set_tags(metadata.tags.clone());
+        self.metadata = Some(metadata);
+    }
+
+    fn set_tags(&mut self, tags: ImageTags) {
+        self.rating = tags.stars();
+        self.tags = tags.tags;
     }
 }
 
+impl ToLua<'_> for TagsDetails {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        // The sidecar is read here on the lua thread to keep it off the main thread
+        let tags = match self {
+            Self::Loaded(tags) => tags,
+            Self::Unloaded(path) => read_sidecar(path.as_deref()).tags,
+        };
+
+        tags.to_lua(ctx)
+    }
+}
+
+/// Read the rating and tags from the sidecar of the image at `path`, if it has one
+fn read_sidecar(path: Option<&Path>) -> ImageTags {
+    path.and_then(|path| read_tags(path).ok())
+        .unwrap_or_default()
+}
+
 impl ToLua<'_> for Metadata {
     fn to_lua(self, ctx: Context) -> LuaResult<Value> {
         let t = ctx.create_table()?;
 
         t.set("dimensions", Dimensions(self.dimensions))?;
         t.set("format", self.format)?;
+        t.set("orientation", self.orientation.exif())?;
+
+        Ok(Value::Table(t))
+    }
+}
+
+impl ToLua<'_> for ExifDetails {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        let t = ctx.create_table()?;
+
+        t.set("make", self.make)?;
+        t.set("model", self.model)?;
+        t.set("lens", self.lens)?;
+        t.set("exposure_time", self.exposure_time)?;
+        t.set("f_number", self.f_number)?;
+        t.set("iso", self.iso)?;
+        t.set("focal_length", self.focal_length)?;
+        t.set("date_taken", self.date_taken)?;
+        t.set("gps", self.gps)?;
+
+        Ok(Value::Table(t))
+    }
+}
+
+impl ToLua<'_> for GpsLocation {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        let t = ctx.create_table()?;
+
+        t.set("latitude", self.latitude)?;
+        t.set("longitude", self.longitude)?;
+        t.set("altitude", self.altitude)?;
 
         Ok(Value::Table(t))
     }
 }
 
+impl ToLua<'_> for FileDetails {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        let t = ctx.create_table()?;
+
+        t.set("size", self.size)?;
+        t.set("modified", self.modified)?;
+        t.set("permissions", self.permissions)?;
+
+        Ok(Value::Table(t))
+    }
+}
+
+impl ToLua<'_> for LoadError {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        let t = ctx.create_table()?;
+
+        t.set("kind", self.kind)?;
+        t.set("message", self.message)?;
+
+        Ok(Value::Table(t))
+    }
+}
+
+impl ToLua<'_> for LoadErrorKind {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        match self {
+            Self::Read => "read",
+            Self::Decode => "decode",
+            Self::External => "external",
+            Self::Limit => "limit",
+            Self::Panic => "panic",
+            Self::Busy => "busy",
+        }
+        .to_lua(ctx)
+    }
+}
+
 struct Dimensions((u32, u32));
 impl ToLua<'_> for Dimensions {
     fn to_lua(self, ctx: Context) -> LuaResult<Value> {
@@ -114,6 +280,104 @@ impl ToLua<'_> for Dimensions {
     }
 }
 
+/// Details of a change to a watched file
+#[derive(Clone, Debug)]
+pub struct FileChangeDetails {
+    /// The kind of change
+    kind: FileChangeKind,
+    /// The path of the file
+    path: PathBuf,
+    /// The index of the image in the image list (`None` if it is not in the list)
+    index: Option<usize>,
+}
+
+impl FileChangeDetails {
+    pub fn new(kind: FileChangeKind, path: PathBuf, index: Option<usize>) -> Self {
+        Self { kind, path, index }
+    }
+}
+
+impl ToLua<'_> for FileChangeDetails {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        let t = ctx.create_table()?;
+
+        t.set("kind", self.kind)?;
+        t.set("path", pathbuf_to_string(self.path))?;
+        t.set("index", self.index.map(|i| i + 1))?;
+
+        Ok(Value::Table(t))
+    }
+}
+
+impl ToLua<'_> for FileChangeKind {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        match self {
+            Self::Modify => "modify",
+            Self::Create => "create",
+            Self::Remove => "remove",
+        }
+        .to_lua(ctx)
+    }
+}
+
+/// Details of an image that failed to load
+#[derive(Clone, Debug)]
+pub struct LoadFailedDetails {
+    /// The index of the image in the image list
+    index: usize,
+    /// The path of the image
+    path: PathBuf,
+    /// The error from loading the image
+    error: LoadError,
+}
+
+impl LoadFailedDetails {
+    pub fn new(index: usize, path: PathBuf, error: LoadError) -> Self {
+        Self { index, path, error }
+    }
+}
+
+impl ToLua<'_> for LoadFailedDetails {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        let t = ctx.create_table()?;
+
+        t.set("index", self.index + 1)?;
+        t.set("path", pathbuf_to_string(self.path))?;
+        t.set("error", self.error)?;
+
+        Ok(Value::Table(t))
+    }
+}
+
+/// Details of the last viewed image of the image list
+#[derive(Debug)]
+pub struct HistoryDetails {
+    /// The index of the image in the image list, if it is in the list
+    index: Option<usize>,
+    /// The absolute path of the image
+    path: PathBuf,
+    /// When the image was viewed, in seconds since the unix epoch
+    time: u64,
+}
+
+impl HistoryDetails {
+    pub fn new(index: Option<usize>, path: PathBuf, time: u64) -> Self {
+        Self { index, path, time }
+    }
+}
+
+impl ToLua<'_> for HistoryDetails {
+    fn to_lua(self, ctx: Context) -> LuaResult<Value> {
+        let t = ctx.create_table()?;
+
+        t.set("index", self.index.map(|i| i + 1))?;
+        t.set("path", pathbuf_to_string(self.path))?;
+        t.set("time", self.time)?;
+
+        Ok(Value::Table(t))
+    }
+}
+
 /// Details of the image transform
 #[derive(Debug)]
 pub struct TransformDetails {
@@ -256,6 +520,49 @@ impl FromStr for StatusBarPosition {
     }
 }
 
+impl FromLua<'_> for ToneMapOperator {
+    fn from_lua(v: Value, _: Context) -> LuaResult<Self> {
+        parse_lua_str(v)
+    }
+}
+
+impl FromStr for ToneMapOperator {
+    type Err = StrError;
+
+    fn from_str(s: &str) -> Result<Self, StrError> {
+        match s {
+            "clamp" => Ok(Self::Clamp),
+            "reinhard" => Ok(Self::Reinhard),
+            "aces" => Ok(Self::Aces),
+
+            _ => Err(StrError(format!("Invalid tone mapping operator `{}`", s))),
+        }
+    }
+}
+
+impl FromLua<'_> for SortKey {
+    fn from_lua(v: Value, _: Context) -> LuaResult<Self> {
+        parse_lua_str(v)
+    }
+}
+
+impl FromStr for SortKey {
+    type Err = StrError;
+
+    fn from_str(s: &str) -> Result<Self, StrError> {
+        match s {
+            "name" => Ok(Self::Name),
+            "mtime" => Ok(Self::Modified),
+            "size" => Ok(Self::Size),
+            "dimensions" => Ok(Self::Dimensions),
+            "date" => Ok(Self::DateTaken),
+            "rating" => Ok(Self::Rating),
+
+            _ => Err(StrError(format!("Invalid sort key `{}`", s))),
+        }
+    }
+}
+
 /// Wrapper around `femtovg::Color` for `FromLua` implementation
 #[derive(Debug)]
 pub struct Color(pub femtovg::Color);
@@ -0,0 +1,234 @@
+//! Module for decoding images with external commands
+//!
+//! External loaders are a fallback for formats that rlens cannot decode itself.
+//! Each loader pairs a pattern with a command that writes the decoded image to stdout in a format
+//! that rlens supports (e.g. PNG or PPM).
+
+use crate::image::{ImageSource, LoadError, LoadErrorKind};
+use crate::image_loader::{check_dimensions, DecodeLimits};
+use crate::util::glob_match;
+
+use image::io::{Limits, Reader as ImageReader};
+use image::{DynamicImage, ImageError};
+use std::ffi::OsStr;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// The set of external loaders
+#[derive(Debug)]
+pub struct ExternalLoaders {
+    loaders: Vec<ExternalLoader>,
+    /// The time a command may run for before it is killed
+    timeout: Duration,
+}
+
+/// A command for decoding images that match a pattern
+#[derive(Debug)]
+struct ExternalLoader {
+    /// Glob to match against the filename (e.g. `*.svg`) or the MIME type (e.g. `video/*`)
+    pattern: String,
+    /// The program and its arguments
+    /// Arguments of `%f` are replaced with the path of the image
+    command: Vec<String>,
+}
+
+/// Argument that is substituted for the path of the image
+const PATH_ARG: &str = "%f";
+
+impl ExternalLoaders {
+    pub fn new(
+        loaders: impl IntoIterator<Item = (String, Vec<String>)>,
+        timeout: Duration,
+    ) -> Self {
+        let loaders = loaders
+            .into_iter()
+            .map(|(pattern, command)| ExternalLoader { pattern, command })
+            .collect();
+
+        Self { loaders, timeout }
+    }
+
+    /// No external loaders
+    pub fn none() -> Self {
+        Self::new([], Duration::ZERO)
+    }
+
+    /// Check if any loader matches the image at `path`
+    pub fn matches(&self, path: &Path) -> bool {
+        self.matching(path).next().is_some()
+    }
+
+    /// Decode the image at `path` with the matching loaders
+    /// Each matching loader is tried in turn until one succeeds
+    /// The output is decoded within `limits` if provided, and exceeding them stops the search
+    pub fn load(
+        &self,
+        path: &Path,
+        limits: Option<&DecodeLimits>,
+    ) -> Result<DynamicImage, LoadError> {
+        let mut errors = Vec::new();
+
+        for loader in self.matching(path) {
+            match loader.load(path, self.timeout, limits) {
+                Ok(image) => return Ok(image),
+                Err(e) if e.kind == LoadErrorKind::Limit => return Err(e),
+                Err(e) => errors.push(e.message),
+            }
+        }
+
+        let message = if errors.is_empty() {
+            format!("No external loader matches `{}`", path.display())
+        } else {
+            errors.join("\n")
+        };
+        Err(LoadError::new(LoadErrorKind::External, message))
+    }
+
+    /// Iterator over the loaders that match the image at `path`
+    fn matching<'a>(&'a self, path: &'a Path) -> impl Iterator<Item = &'a ExternalLoader> {
+        self.loaders.iter().filter(move |l| l.matches(path))
+    }
+}
+
+impl ExternalLoader {
+    /// Check if the loader matches the image at `path`
+    fn matches(&self, path: &Path) -> bool {
+        if self.pattern.contains('/') {
+            // Match against the MIME types guessed from the extension
+            mime_guess::from_path(path)
+                .iter()
+                .any(|mime| glob_match(&self.pattern, mime.essence_str()))
+        } else {
+            // Match against the filename
+            path.file_name()
+                .and_then(OsStr::to_str)
+                .map(|name| glob_match(&self.pattern.to_lowercase(), &name.to_lowercase()))
+                .unwrap_or(false)
+        }
+    }
+
+    /// Run the command on the image at `path` and decode its output within `limits`
+    fn load(
+        &self,
+        path: &Path,
+        timeout: Duration,
+        limits: Option<&DecodeLimits>,
+    ) -> Result<DynamicImage, LoadError> {
+        let data = self
+            .run(path, timeout)
+            .map_err(|e| LoadError::new(LoadErrorKind::External, e))?;
+
+        let output_err = |e: ImageError| {
+            let kind = match e {
+                ImageError::Limits(_) => LoadErrorKind::Limit,
+                _ => LoadErrorKind::External,
+            };
+            let message = format!(
+                "Failed to decode the output of `{}` for `{}`: {}",
+                self.command[0],
+                path.display(),
+                e
+            );
+            LoadError::new(kind, message)
+        };
+        let reader = || {
+            ImageReader::new(Cursor::new(&data))
+                .with_guessed_format()
+                .map_err(|e| output_err(ImageError::IoError(e)))
+        };
+
+        if let Some(limits) = limits {
+            let dimensions = reader()?.into_dimensions().map_err(output_err)?;
+            check_dimensions(&ImageSource::File(path.to_path_buf()), dimensions, limits)?;
+        }
+
+        let mut reader = reader()?;
+        let mut reader_limits = Limits::no_limits();
+        reader_limits.max_alloc = limits.and_then(|l| l.max_alloc);
+        reader.limits(reader_limits);
+        reader.decode().map_err(output_err)
+    }
+
+    /// Run the command on the image at `path`
+    /// Returns the contents of stdout if the command succeeds within the timeout
+    fn run(&self, path: &Path, timeout: Duration) -> Result<Vec<u8>, String> {
+        let (program, args) = self
+            .command
+            .split_first()
+            .ok_or_else(|| format!("The loader for `{}` has an empty command", self.pattern))?;
+
+        let args = args.iter().map(|arg| {
+            if arg == PATH_ARG {
+                path.as_os_str()
+            } else {
+                OsStr::new(arg)
+            }
+        });
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run external loader `{}`: {}", program, e))?;
+
+        // Read the output on separate threads so that the pipes do not fill while we wait
+        let stdout = read_pipe(child.stdout.take());
+        let stderr = read_pipe(child.stderr.take());
+
+        // Wait for the command to finish, killing it if the timeout is exceeded
+        const POLL_INTERVAL: Duration = Duration::from_millis(10);
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            let status = child
+                .try_wait()
+                .map_err(|e| format!("Failed to wait on external loader `{}`: {}", program, e))?;
+            if let Some(status) = status {
+                break status;
+            }
+
+            if Instant::now() >= deadline {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!(
+                    "External loader `{}` timed out after {:?} on `{}`",
+                    program,
+                    timeout,
+                    path.display()
+                ));
+            }
+
+            thread::sleep(POLL_INTERVAL);
+        };
+
+        let stdout = stdout.join().unwrap_or_default();
+        let stderr = stderr.join().unwrap_or_default();
+
+        if !status.success() {
+            return Err(format!(
+                "External loader `{}` failed on `{}` ({}): {}",
+                program,
+                path.display(),
+                status,
+                String::from_utf8_lossy(&stderr).trim()
+            ));
+        }
+
+        Ok(stdout)
+    }
+}
+
+/// Read a pipe to its end on a new thread
+fn read_pipe(pipe: Option<impl Read + Send + 'static>) -> JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut data = Vec::new();
+        if let Some(mut pipe) = pipe {
+            pipe.read_to_end(&mut data).ok();
+        }
+        data
+    })
+}
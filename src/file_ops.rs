@@ -0,0 +1,114 @@
+//! Module for operations on the files of images: trashing, moving, copying and renaming
+//!
+//! The sidecar of an image (see `sidecar`) goes along with it.
+//! Existing files are never overwritten.
+
+use crate::sidecar::sidecar_path;
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Move the file at `path` to the trash
+/// On Linux and other freedesktop.org systems, this follows the trash specification
+pub fn trash_file(path: &Path) -> Result<(), String> {
+    let sidecar = sidecar_path(path);
+    let paths = if sidecar.exists() {
+        vec![path.to_path_buf(), sidecar]
+    } else {
+        vec![path.to_path_buf()]
+    };
+
+    trash::delete_all(paths).map_err(|e| file_error("trash", path, e))
+}
+
+/// Move the file at `from` to `to`
+pub fn move_file(from: &Path, to: &Path) -> Result<(), String> {
+    with_sidecar(from, to, |from, to| {
+        ensure_free(to)?;
+        match fs::rename(from, to) {
+            // Files cannot be renamed across filesystems, so they are copied and then removed
+            Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+                fs::copy(from, to).map_err(|e| file_error("move", from, e))?;
+                fs::remove_file(from).map_err(|e| file_error("move", from, e))
+            }
+            result => result.map_err(|e| file_error("move", from, e)),
+        }
+    })
+}
+
+/// Copy the file at `from` to `to`
+pub fn copy_file(from: &Path, to: &Path) -> Result<(), String> {
+    with_sidecar(from, to, |from, to| {
+        ensure_free(to)?;
+        fs::copy(from, to)
+            .map(|_| ())
+            .map_err(|e| file_error("copy", from, e))
+    })
+}
+
+/// The path of the file at `path` in the directory `dir`
+pub fn path_in_dir(path: &Path, dir: &Path) -> Result<PathBuf, String> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| format!("Error: `{}` has no file name", path.display()))?;
+
+    Ok(dir.join(name))
+}
+
+/// The path of the file at `path` renamed to `name`
+/// With `stem`, the file takes only the stem of `name` and keeps its extension, so that the
+/// members of a stack are renamed together
+pub fn renamed_path(path: &Path, name: &str, stem: bool) -> Result<PathBuf, String> {
+    let name = Path::new(name);
+    if name.file_name() != Some(name.as_os_str()) {
+        return Err(format!(
+            "Error: `{}` is not a valid file name",
+            name.display()
+        ));
+    }
+
+    if !stem {
+        return Ok(path.with_file_name(name));
+    }
+
+    let mut renamed = path.with_file_name(name.file_stem().unwrap_or(name.as_os_str()));
+    if let Some(extension) = path.extension() {
+        renamed.set_extension(extension);
+    }
+    Ok(renamed)
+}
+
+/// Apply `op` to the file at `from`, and then to its sidecar if it has one
+fn with_sidecar(
+    from: &Path,
+    to: &Path,
+    op: impl Fn(&Path, &Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let from_sidecar = sidecar_path(from);
+    let to_sidecar = sidecar_path(to);
+    let has_sidecar = from_sidecar.exists();
+    if has_sidecar {
+        ensure_free(&to_sidecar)?;
+    }
+
+    op(from, to)?;
+    if has_sidecar {
+        op(&from_sidecar, &to_sidecar)?;
+    }
+
+    Ok(())
+}
+
+/// Fail if there is a file at `path`
+fn ensure_free(path: &Path) -> Result<(), String> {
+    if path.exists() {
+        Err(format!("Error: `{}` already exists", path.display()))
+    } else {
+        Ok(())
+    }
+}
+
+fn file_error(op: &str, path: &Path, e: impl std::fmt::Display) -> String {
+    format!("Error: Failed to {} `{}`: {}", op, path.display(), e)
+}
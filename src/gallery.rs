@@ -3,8 +3,11 @@
 use crate::geometry::*;
 use crate::gfx::{CanvasExt, Gfx};
 use crate::image::{Image, LoadedImage};
+use crate::image_transform::Orientation;
+use crate::util::PrintErr;
 
-use femtovg::Color;
+use femtovg::{Align, Color};
+use std::mem;
 
 pub struct Gallery {
     /// The current position in the gallery
@@ -28,6 +31,17 @@ pub struct Gallery {
     cursor_color: Color,
     /// The color of the placeholder borders
     placeholder_border_color: Color,
+    /// The color of the tiles of images that failed to load
+    error_color: Color,
+    /// The background color of the badges on stacked images
+    badge_color: Color,
+    /// The color of the indicators on marked images
+    mark_color: Color,
+    /// The color of the rating pips on rated images
+    rating_color: Color,
+
+    /// Whether thumbnails are oriented by their image's EXIF orientation
+    auto_orient: bool,
 }
 
 impl Gallery {
@@ -39,6 +53,11 @@ impl Gallery {
             height_width_ratio: 1.0,
             cursor_color: Color::white(),
             placeholder_border_color: Color::white(),
+            error_color: Color::rgb(200, 40, 40),
+            badge_color: Color::rgba(0, 0, 0, 180),
+            mark_color: Color::rgb(250, 200, 40),
+            rating_color: Color::white(),
+            auto_orient: true,
         }
     }
 }
@@ -47,6 +66,19 @@ impl Gallery {
         self.cursor
     }
 
+    /// The index of the first image in view
+    pub fn anchor(&self) -> usize {
+        self.anchor
+    }
+
+    /// Set the cursor and anchor restored from a session
+    /// The anchor is moved if the cursor would be out of view
+    /// Pre: `cursor` and `anchor` are valid
+    pub fn restore(&mut self, cursor: usize, anchor: usize, view: Size) {
+        self.anchor = anchor;
+        self.set_cursor(cursor, view);
+    }
+
     /// Set the cursor in the gallery
     /// Pre: `index` is valid
     pub fn set_cursor(&mut self, index: usize, view: Size) {
@@ -55,6 +87,40 @@ impl Gallery {
         self.cursor = index;
     }
 
+    /// Exchange the cursor and scroll with another gallery, such as when switching between
+    /// image lists
+    /// The settings of the galleries are kept
+    pub fn swap_position(&mut self, other: &mut Self, view: Size) {
+        mem::swap(&mut self.cursor, &mut other.cursor);
+        mem::swap(&mut self.anchor, &mut other.anchor);
+        self.set_cursor(self.cursor, view);
+    }
+
+    /// Update the cursor after the image at `index` was removed from the image list
+    /// `total` is the new number of images (> 0)
+    pub fn on_remove(&mut self, index: usize, total: usize, view: Size) {
+        assert!(total > 0);
+
+        let cursor = if self.cursor > index {
+            self.cursor - 1
+        } else {
+            self.cursor.min(total - 1)
+        };
+
+        self.anchor = self.anchor.min(total - 1);
+        self.set_cursor(cursor, view);
+    }
+
+    /// Update the cursor after the image list was rebuilt
+    /// `total` is the new number of images (> 0)
+    /// Pre: `index` is valid
+    pub fn on_rebuild(&mut self, index: usize, total: usize, view: Size) {
+        assert!(total > 0);
+
+        self.anchor = self.anchor.min(total - 1);
+        self.set_cursor(index, view);
+    }
+
     /// Update the anchor to satisfy a new cursor index
     /// Pre: `index` is valid
     fn update_anchor(&mut self, index: usize, view: Size) {
@@ -109,11 +175,39 @@ impl Gallery {
         self.placeholder_border_color = color;
     }
 
+    pub fn set_error_color(&mut self, color: Color) {
+        self.error_color = color;
+    }
+
+    pub fn set_badge_color(&mut self, color: Color) {
+        self.badge_color = color;
+    }
+
+    pub fn set_mark_color(&mut self, color: Color) {
+        self.mark_color = color;
+    }
+
+    pub fn set_rating_color(&mut self, color: Color) {
+        self.rating_color = color;
+    }
+
+    pub fn set_auto_orient(&mut self, auto_orient: bool) {
+        self.auto_orient = auto_orient;
+    }
+
     /// Calculate the number of tiles in a row of the gallery
     pub fn tiles_in_row(&self, view: Size) -> usize {
         self.tiling(view).map(|t| t.tiles_in_row).unwrap_or(0)
     }
 
+    /// Get the size in pixels of the largest side of the thumbnail areas (inner tiles)
+    pub fn thumbnail_size(&self, view: Size) -> Option<u32> {
+        let tiling = self.tiling(view)?;
+        let width = tiling.tile_width - INNER_TILE_GAP * 2.0;
+        let height = tiling.tile_height - INNER_TILE_GAP * 2.0;
+        Some(width.max(height).max(0.0).ceil() as u32)
+    }
+
     /// Get the range of images that should be loaded
     /// Returns the index of the first image, and the total number of images to load
     pub fn load_range(&self, view: Size) -> Option<(usize, usize)> {
@@ -124,6 +218,9 @@ impl Gallery {
 
 // === Tiling ===
 
+/// Width of the gap around the thumbnail areas (inner tiles)
+const INNER_TILE_GAP: f32 = 5.0;
+
 /// Gallery tiling details
 struct Tiling {
     /// The number of tiles in a row of the gallery
@@ -196,8 +293,6 @@ impl Gallery {
         let tile_offset =
             |row, col| tile_offset(row, col, tiling.tile_width, tiling.tile_height) + grid_offset;
 
-        /// Width of the gap around the thumbnail areas (inner tiles)
-        const INNER_TILE_GAP: f32 = 5.0;
         let inner_tile_bounds = tile_bounds.inner_box(SideOffsets::new_all_same(INNER_TILE_GAP));
         if inner_tile_bounds.is_empty() {
             return;
@@ -232,29 +327,67 @@ impl Gallery {
             }
         }
 
-        // Iterator over the thumbnails for each visible image
-        let thumbnails = images
-            .iter()
-            .skip(first)
-            .take(tiles)
-            .map(|image| &image.thumbnail);
+        // Iterator over the visible images
+        let images = images.iter().skip(first).take(tiles);
 
         // Draw the tiles
-        for (thumbnail, inner_tile) in thumbnails.zip(inner_tiles) {
-            if let Some(thumbnail) = thumbnail.loaded() {
+        for (image, inner_tile) in images.zip(inner_tiles) {
+            if let Some(thumbnail) = image.thumbnail.loaded() {
+                let orientation = if self.auto_orient {
+                    image.orientation()
+                } else {
+                    Orientation::default()
+                };
+
                 // Draw the thumbnail in the inner tile
-                draw_thumbnail(thumbnail, backdrop_color, inner_tile, gfx);
+                draw_thumbnail(thumbnail, orientation, backdrop_color, inner_tile, gfx);
+            } else if image.is_unloadable() {
+                // The image failed to load so mark the tile as failed
+                draw_failed(inner_tile, self.error_color, gfx);
             } else {
                 // The thumbnail is not loaded so draw a placeholder instead
                 draw_placeholder(inner_tile, self.placeholder_border_color, gfx);
             }
+
+            // Show the number of members of stacked images
+            if image.stack_size() > 1 {
+                draw_badge(
+                    &image.stack_size().to_string(),
+                    self.badge_color,
+                    inner_tile,
+                    gfx,
+                );
+            }
+
+            if image.is_marked() {
+                draw_mark(self.mark_color, inner_tile, gfx);
+            }
+
+            // Show the rating of rated images once their metadata is loaded
+            if let Some(metadata) = image.metadata.loaded() {
+                if metadata.tags.stars() > 0 {
+                    draw_rating(
+                        metadata.tags.stars(),
+                        self.rating_color,
+                        self.badge_color,
+                        inner_tile,
+                        gfx,
+                    );
+                }
+            }
         }
     }
 }
 
-/// Draw the thumbnail within the given bounds, centered and not stretched
-fn draw_thumbnail(thumbnail: &LoadedImage, backdrop_color: Color, bounds: Rect, gfx: &mut Gfx) {
-    let thumbnail_size = thumbnail.size();
+/// Draw the oriented thumbnail within the given bounds, centered and not stretched
+fn draw_thumbnail(
+    thumbnail: &LoadedImage,
+    orientation: Orientation,
+    backdrop_color: Color,
+    bounds: Rect,
+    gfx: &mut Gfx,
+) {
+    let thumbnail_size = orientation.oriented_size(thumbnail.size());
 
     if thumbnail_size.is_empty() {
         return;
@@ -273,14 +406,22 @@ fn draw_thumbnail(thumbnail: &LoadedImage, backdrop_color: Color, bounds: Rect,
         (bounds.height() - scaled_thumbnail_size.height) / 2.0,
     );
 
-    // The true bounds of the thumbnail within the tile
-    let image_bounds = Rect::from_origin_and_size(bounds.min + offset, scaled_thumbnail_size);
+    // The transform from the raw thumbnail to its place within the tile
+    let transform = orientation
+        .transform(thumbnail.size())
+        .then_scale(scale_factor, scale_factor)
+        .then_translate(bounds.min.to_vector() + offset);
+    let raw_bounds = Rect::from_size(thumbnail.size());
+
+    gfx.canvas.save_with(|canvas| {
+        canvas.set_transform_(transform);
 
-    // Draw the backdrop
-    gfx.canvas.draw_rect(image_bounds, backdrop_color);
+        // Draw the backdrop
+        canvas.draw_rect(raw_bounds, backdrop_color);
 
-    // Draw the thumbnail
-    gfx.canvas.draw_image(thumbnail.id(), image_bounds);
+        // Draw the thumbnail
+        canvas.draw_image(thumbnail.id(), raw_bounds);
+    });
 }
 
 /// Highlight the tile given its bounds
@@ -301,6 +442,93 @@ fn draw_placeholder(bounds: Rect, border_color: Color, gfx: &mut Gfx) {
         .draw_rect_outline(bounds, BORDER_WIDTH, border_color);
 }
 
+/// Draw a badge with the text in the top-right corner of the bounds
+fn draw_badge(text: &str, color: Color, bounds: Rect, gfx: &mut Gfx) {
+    /// Padding around the text of the badge
+    const PADDING: f32 = 4.0;
+
+    let text_width = match gfx.canvas.text_width(text, &gfx.font).print_err() {
+        Ok(width) => width,
+        _ => return,
+    };
+
+    let size = Size::new(text_width + PADDING * 2.0, gfx.font.height() + PADDING);
+    if size.width > bounds.width() || size.height > bounds.height() {
+        return;
+    }
+
+    let badge = Rect::new(
+        Point::new(bounds.max.x - size.width, bounds.min.y),
+        Point::new(bounds.max.x, bounds.min.y + size.height),
+    );
+    gfx.canvas.draw_rect(badge, color);
+
+    let text_bounds = badge.inner_box(SideOffsets::new(PADDING / 2.0, PADDING, 0.0, PADDING));
+    gfx.canvas
+        .draw_text(text, &gfx.font, text_bounds, Align::Left)
+        .print_err()
+        .ok();
+}
+
+/// Draw the indicator of a marked image in the top-left corner of the bounds
+fn draw_mark(color: Color, bounds: Rect, gfx: &mut Gfx) {
+    /// Side length of the indicator
+    const SIZE: f32 = 12.0;
+
+    if SIZE > bounds.width() || SIZE > bounds.height() {
+        return;
+    }
+
+    let mark = Rect::new(bounds.min, bounds.min + Vector::new(SIZE, SIZE));
+    gfx.canvas.draw_rect(mark, color);
+}
+
+/// Draw a row of pips for the rating in the bottom-left corner of the bounds
+fn draw_rating(rating: u8, color: Color, bg_color: Color, bounds: Rect, gfx: &mut Gfx) {
+    /// Side length of each pip
+    const PIP_SIZE: f32 = 8.0;
+    /// Gap around the pips
+    const GAP: f32 = 3.0;
+
+    let pips = f32::from(rating);
+    let size = Size::new(pips * (PIP_SIZE + GAP) + GAP, PIP_SIZE + GAP * 2.0);
+    if size.width > bounds.width() || size.height > bounds.height() {
+        return;
+    }
+
+    let background = Rect::new(
+        Point::new(bounds.min.x, bounds.max.y - size.height),
+        Point::new(bounds.min.x + size.width, bounds.max.y),
+    );
+    gfx.canvas.draw_rect(background, bg_color);
+
+    for i in 0..rating {
+        let min = background.min + Vector::new(GAP + f32::from(i) * (PIP_SIZE + GAP), GAP);
+        let pip = Rect::new(min, min + Vector::new(PIP_SIZE, PIP_SIZE));
+        gfx.canvas.draw_rect(pip, color);
+    }
+}
+
+/// Draw a crossed out placeholder in the inner bounds of a tile
+fn draw_failed(bounds: Rect, color: Color, gfx: &mut Gfx) {
+    /// Width of the lines of the placeholder
+    const LINE_WIDTH: f32 = 2.0;
+
+    if bounds.width() < LINE_WIDTH * 2.0 || bounds.height() < LINE_WIDTH * 2.0 {
+        return;
+    }
+
+    gfx.canvas.draw_rect_outline(bounds, LINE_WIDTH, color);
+
+    // Cross out the tile
+    let top_right = Point::new(bounds.max.x, bounds.min.y);
+    let bottom_left = Point::new(bounds.min.x, bounds.max.y);
+    gfx.canvas
+        .draw_line(bounds.min, bounds.max, LINE_WIDTH, color);
+    gfx.canvas
+        .draw_line(top_right, bottom_left, LINE_WIDTH, color);
+}
+
 /// Calculate the offset of a tile from the top-left of the grid
 /// The top-left tile has an offset of 0
 fn tile_offset(row: usize, col: usize, tile_width: f32, tile_height: f32) -> Vector {
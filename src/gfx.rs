@@ -116,6 +116,8 @@ pub trait CanvasExt {
 
     fn draw_rect_outline(&mut self, rect: Rect, line_width: f32, color: Color);
 
+    fn draw_line(&mut self, from: Point, to: Point, line_width: f32, color: Color);
+
     fn draw_image(&mut self, image: ImageId, bounds: Rect);
 
     fn draw_text(
@@ -126,11 +128,20 @@ pub trait CanvasExt {
         align: Align,
     ) -> FemtovgResult<f32>;
 
+    fn text_width(&self, text: &str, font: &Font) -> FemtovgResult<f32>;
+
     fn register_image(
         &mut self,
         image_data: &[u8],
         dimentions: (u32, u32),
     ) -> FemtovgResult<ImageId>;
+
+    fn replace_image_data(
+        &mut self,
+        image: ImageId,
+        image_data: &[u8],
+        dimentions: (u32, u32),
+    ) -> FemtovgResult<()>;
 }
 
 impl CanvasExt for Canvas {
@@ -165,6 +176,17 @@ impl CanvasExt for Canvas {
         self.stroke_path(&mut path, &paint);
     }
 
+    fn draw_line(&mut self, from: Point, to: Point, line_width: f32, color: Color) {
+        let mut path = Path::new();
+        path.move_to(from.x, from.y);
+        path.line_to(to.x, to.y);
+
+        let mut paint = Paint::color(color);
+        paint.set_line_width(line_width);
+
+        self.stroke_path(&mut path, &paint);
+    }
+
     fn draw_image(&mut self, image: ImageId, bounds: Rect) {
         let mut path = Path::new();
         path.rect(bounds.min.x, bounds.min.y, bounds.width(), bounds.height());
@@ -218,6 +240,15 @@ impl CanvasExt for Canvas {
         metrics.map(|m| m.width())
     }
 
+    /// Measure the width of text drawn with `draw_text`
+    fn text_width(&self, text: &str, font: &Font) -> FemtovgResult<f32> {
+        let mut paint = Paint::default();
+        paint.set_font(&[font.id]);
+        paint.set_font_size(font.size);
+
+        self.measure_text(0.0, 0.0, text, &paint).map(|m| m.width())
+    }
+
     /// Register an image into the canvas
     /// * `image_data`: The image data in RGB8 pixels
     fn register_image(
@@ -235,4 +266,18 @@ impl CanvasExt for Canvas {
 
         self.create_image(source, flags)
     }
+
+    fn replace_image_data(
+        &mut self,
+        image: ImageId,
+        image_data: &[u8],
+        dimentions: (u32, u32),
+    ) -> FemtovgResult<()> {
+        let (width, height) = dimentions;
+
+        let pixels = image_data.as_pixels();
+        let img = Img::new(pixels, width as usize, height as usize);
+
+        self.update_image(image, ImageSource::Rgba(img), 0, 0)
+    }
 }
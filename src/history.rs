@@ -0,0 +1,96 @@
+//! Module for the history of viewed image lists, used to resume where an image list was left
+//!
+//! Image lists are identified by their source: the deepest directory containing all of their
+//! images. Each source has an entry in the history directory, named by the hash of its path.
+
+use crate::util::{absolute_path, hash_filepath, write_atomically};
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The history of viewed image lists
+pub struct History {
+    /// The directory of the entries
+    dir: PathBuf,
+}
+
+/// The last viewed image of an image list
+#[derive(Serialize, Deserialize, Debug)]
+pub struct HistoryEntry {
+    /// The source of the image list
+    pub source: PathBuf,
+    /// The absolute path of the last viewed image
+    pub path: PathBuf,
+    /// When the image was viewed, in seconds since the unix epoch
+    pub time: u64,
+}
+
+impl History {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Get the entry for the image list with the given paths
+    pub fn get<'a>(&self, paths: impl IntoIterator<Item = &'a Path>) -> Option<HistoryEntry> {
+        let source = list_source(paths)?;
+        let text = fs::read_to_string(self.entry_path(&source)).ok()?;
+        let entry: HistoryEntry = toml::from_str(&text).ok()?;
+
+        // Guard against hash collisions
+        (entry.source == source).then_some(entry)
+    }
+
+    /// Record `current` as the last viewed image of the image list with the given paths
+    pub fn record<'a>(
+        &self,
+        paths: impl IntoIterator<Item = &'a Path>,
+        current: &Path,
+    ) -> Result<(), String> {
+        let source = match list_source(paths) {
+            Some(source) => source,
+            None => return Ok(()),
+        };
+
+        let entry = HistoryEntry {
+            path: absolute_path(current),
+            source,
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+        };
+        let text = toml::to_string(&entry)
+            .map_err(|e| format!("Error: Failed to serialize the history: {}", e))?;
+
+        write_atomically(&self.entry_path(&entry.source), |temp_path| {
+            fs::write(temp_path, text).map_err(|e| e.to_string())
+        })
+    }
+
+    /// The path of the entry for a source
+    fn entry_path(&self, source: &Path) -> PathBuf {
+        self.dir.join(hash_filepath(source))
+    }
+}
+
+/// The source of an image list: the deepest directory containing all of its images
+/// `None` if the image list has no images on the disk
+fn list_source<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut source: Option<PathBuf> = None;
+
+    for path in paths {
+        let path = absolute_path(path);
+        let dir = path.parent()?;
+
+        source = Some(match source {
+            None => dir.to_path_buf(),
+            Some(source) => source
+                .ancestors()
+                .find(|ancestor| dir.starts_with(ancestor))?
+                .to_path_buf(),
+        });
+    }
+
+    source
+}
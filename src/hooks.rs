@@ -1,5 +1,6 @@
 //! Module for lua hooks
 
+use crate::command_types::{FileChangeDetails, LoadFailedDetails};
 use crate::lua::LuaContext;
 use crate::util::PrintLuaErr;
 
@@ -33,24 +34,39 @@ impl Hooks {
 
 /// A hook that is triggered by an external event
 pub enum ExternalHook {
+    /// The current image was changed
+    CurrentImageChange,
     /// The current image was loaded
     CurrentImageLoad,
     /// The window was resized
     WindowResize,
+    /// A watched file was changed
+    FileChange(FileChangeDetails),
+    /// An image failed to load
+    ImageLoadFailed(LoadFailedDetails),
 }
 
 impl ExternalHook {
     /// Run the hook
     pub fn run(&self, lua_ctx: LuaContext) {
         let name = self.name();
-        lua_ctx.call_hook(name).print_lua_err().ok();
+        match self {
+            Self::FileChange(details) => lua_ctx.call_hook_with(name, details.clone()),
+            Self::ImageLoadFailed(details) => lua_ctx.call_hook_with(name, details.clone()),
+            _ => lua_ctx.call_hook(name),
+        }
+        .print_lua_err()
+        .ok();
     }
 
     /// The name of the hook under lua
     fn name(&self) -> &'static str {
         match self {
+            Self::CurrentImageChange => "current_image_change",
             Self::CurrentImageLoad => "current_image_load",
             Self::WindowResize => "resize",
+            Self::FileChange(_) => "file_change",
+            Self::ImageLoadFailed(_) => "image_load_failed",
         }
     }
 }
@@ -2,29 +2,98 @@
 
 use crate::geometry::*;
 use crate::gfx::{CanvasExt, Gfx};
+use crate::image_transform::Orientation;
+use crate::phash::ImageHash;
+use crate::sidecar::ImageTags;
+use crate::tone_map::{HdrImage, ToneMapping};
 
 use femtovg::ImageId;
+use std::fmt::{self, Debug, Display};
 use std::mem;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 
 /// An image in the image list
 pub struct Image {
-    /// The path of the source image
-    path: PathBuf,
+    /// Identifier of the image
+    key: ImageKey,
+    /// The source of the image data
+    /// This is the primary member of the image's stack
+    source: ImageSource,
+    /// The other members of the image's stack, in order after the primary member
+    stack: Vec<ImageSource>,
     /// Information about the full image
     pub full: LoadState<LoadedImage>,
     /// Information about the thumbnail
     pub thumbnail: LoadState<LoadedImage>,
+    /// The size that the loaded thumbnail was requested at
+    pub thumbnail_size: u32,
     /// Metadata information
     pub metadata: LoadState<Metadata>,
-    /// Whether the source is known to be unloadable
-    unloadable: bool,
+    /// The perceptual hash of the image, if computed
+    hash: Option<ImageHash>,
+    /// The error from the last failed load
+    /// The source is known to be unloadable while this is present
+    error: Option<LoadError>,
+    /// Whether the image should be loaded regardless of the decoding limits
+    ignore_limits: bool,
+    /// Whether the image is marked
+    marked: bool,
+}
+
+/// An identifier that is unique to each image
+/// Used to track an image when its index may have changed
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ImageKey(u64);
+
+/// The source of an image's data
+#[derive(Clone)]
+pub enum ImageSource {
+    /// An image file on the disk
+    File(PathBuf),
+    /// Image data held in memory
+    /// Used for sources that can only be read once (e.g. stdin or a named pipe)
+    Memory {
+        /// The path the data was read from
+        path: PathBuf,
+        /// The raw (encoded) image data
+        data: Arc<[u8]>,
+    },
+}
+
+/// An error from loading an image
+#[derive(Clone, Debug)]
+pub struct LoadError {
+    pub kind: LoadErrorKind,
+    /// Description of the error
+    pub message: String,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum LoadErrorKind {
+    /// The source could not be read
+    Read,
+    /// The image data could not be decoded
+    Decode,
+    /// The image could not be decoded, and neither could the external loaders load it
+    External,
+    /// The image exceeded the decoding limits
+    Limit,
+    /// The loader panicked while loading the image
+    Panic,
+    /// The loader was busy with a decode that exceeded the time limit
+    /// The load should be retried, so this is never stored on an image
+    Busy,
 }
 
 /// An item that may or may not be loaded
 pub enum LoadState<T> {
     Unloaded,
     Loaded(T),
+    /// Loaded, but out of date with the source
+    /// The item should be used until it is replaced
+    Stale(T),
 }
 
 /// An image that has been loaded into the canvas
@@ -33,6 +102,8 @@ pub struct LoadedImage {
     id: ImageId,
     /// The dimensions of the image
     size: Size,
+    /// The high precision data of the image, if retained
+    hdr: Option<HdrImage>,
 }
 
 /// Metadata for an image
@@ -42,63 +113,305 @@ pub struct Metadata {
     pub dimensions: (u32, u32),
     /// A string representation of the format of the image
     /// e.g. "png"
-    pub format: Option<&'static str>,
+    pub format: Option<String>,
+    /// The orientation of the image's pixel data
+    pub orientation: Orientation,
+    /// Details from the EXIF data of the image, if present
+    pub exif: Option<ExifDetails>,
+    /// Details of the image's file, if read from the disk
+    pub file: Option<FileDetails>,
+    /// The rating and tags from the image's sidecar
+    pub tags: ImageTags,
+    /// Whether the image was loaded by an external loader
+    pub external: bool,
+}
+
+/// Details from the EXIF data of an image
+#[derive(Clone, Default, Debug)]
+pub struct ExifDetails {
+    /// The manufacturer of the camera
+    pub make: Option<String>,
+    /// The model of the camera
+    pub model: Option<String>,
+    /// The model of the lens
+    pub lens: Option<String>,
+    /// The exposure time in seconds, as displayed (e.g. "1/250")
+    pub exposure_time: Option<String>,
+    /// The f-number of the aperture
+    pub f_number: Option<f64>,
+    /// The ISO sensitivity
+    pub iso: Option<u32>,
+    /// The focal length in millimetres
+    pub focal_length: Option<f64>,
+    /// The date and time the image was taken (e.g. "2023-05-01 12:34:56")
+    pub date_taken: Option<String>,
+    /// The location the image was taken
+    pub gps: Option<GpsLocation>,
+}
+
+/// A GPS location
+#[derive(Clone, Debug)]
+pub struct GpsLocation {
+    /// Latitude in degrees (north is positive)
+    pub latitude: f64,
+    /// Longitude in degrees (east is positive)
+    pub longitude: f64,
+    /// Altitude in metres above sea level
+    pub altitude: Option<f64>,
+}
+
+/// Details of an image's file
+#[derive(Clone, Debug)]
+pub struct FileDetails {
+    /// The size of the file in bytes
+    pub size: u64,
+    /// The time of the last modification in seconds since the unix epoch
+    pub modified: Option<i64>,
+    /// The permissions of the file (e.g. "rw-r--r--")
+    pub permissions: String,
 }
 
 impl Image {
-    pub fn new_unloaded(path: PathBuf) -> Self {
+    pub fn new_unloaded(source: ImageSource) -> Self {
+        static NEXT_KEY: AtomicU64 = AtomicU64::new(0);
+
         Self {
-            path,
+            key: ImageKey(NEXT_KEY.fetch_add(1, Ordering::Relaxed)),
+            source,
+            stack: Vec::new(),
             full: LoadState::Unloaded,
             thumbnail: LoadState::Unloaded,
+            thumbnail_size: 0,
             metadata: LoadState::Unloaded,
-            unloadable: false,
+            hash: None,
+            error: None,
+            ignore_limits: false,
+            marked: false,
         }
     }
 
+    pub fn key(&self) -> ImageKey {
+        self.key
+    }
+
+    pub fn source(&self) -> &ImageSource {
+        &self.source
+    }
+
     pub fn path(&self) -> &Path {
-        &self.path
+        self.source.path()
+    }
+
+    /// The number of members in the image's stack (`1` if the image is not stacked)
+    pub fn stack_size(&self) -> usize {
+        1 + self.stack.len()
+    }
+
+    /// Iterator over the members of the image's stack, starting with the primary member
+    pub fn stack_members(&self) -> impl Iterator<Item = &ImageSource> {
+        std::iter::once(&self.source).chain(&self.stack)
+    }
+
+    /// Mutable iterator over the members of the image's stack, starting with the primary member
+    pub fn stack_members_mut(&mut self) -> impl Iterator<Item = &mut ImageSource> {
+        std::iter::once(&mut self.source).chain(&mut self.stack)
+    }
+
+    /// Add a member to the end of the image's stack
+    pub fn add_to_stack(&mut self, source: ImageSource) {
+        self.stack.push(source);
+    }
+
+    /// Remove the members of the image's stack, other than the primary member
+    pub fn take_stack(&mut self) -> Vec<ImageSource> {
+        mem::take(&mut self.stack)
+    }
+
+    /// Remove the members of the image's stack that satisfy `remove`
+    /// If the primary member is removed, the first remaining member takes its place
+    /// Returns whether the primary member changed
+    /// The loaded state of the previous member should be discarded if it changed
+    /// Pre: some member is kept
+    pub fn remove_stack_members(&mut self, remove: impl Fn(&ImageSource) -> bool) -> bool {
+        self.stack.retain(|source| !remove(source));
+        if !remove(&self.source) {
+            return false;
+        }
+
+        assert!(!self.stack.is_empty());
+        self.source = self.stack.remove(0);
+        true
+    }
+
+    /// Make the next member of the stack the primary member
+    /// The previous primary member moves to the end of the stack
+    /// Returns whether the primary member changed
+    /// The loaded state of the previous member should be discarded
+    pub fn rotate_stack(&mut self) -> bool {
+        if self.stack.is_empty() {
+            return false;
+        }
+
+        let next = self.stack.remove(0);
+        let prev = mem::replace(&mut self.source, next);
+        self.stack.push(prev);
+        true
+    }
+
+    /// Unload the image and take its source
+    pub fn into_source(mut self, gfx: &mut Gfx) -> ImageSource {
+        self.full.unload_image(gfx);
+        self.thumbnail.unload_image(gfx);
+        self.source
+    }
+
+    /// The orientation of the image, if known
+    pub fn orientation(&self) -> Orientation {
+        self.metadata
+            .loaded()
+            .map(|metadata| metadata.orientation)
+            .unwrap_or_default()
+    }
+
+    pub fn hash(&self) -> Option<ImageHash> {
+        self.hash
+    }
+
+    pub fn set_hash(&mut self, hash: ImageHash) {
+        self.hash = Some(hash);
+    }
+
+    /// Forget the hash, such as when the source has changed
+    pub fn forget_hash(&mut self) {
+        self.hash = None;
     }
 
     /// Whether the source image is known to be unloadable
     pub fn is_unloadable(&self) -> bool {
-        self.unloadable
+        self.error.is_some()
     }
 
-    /// Mark the source image as unloadable
-    pub fn mark_unloadable(&mut self) {
-        self.unloadable = true;
+    /// The error that made the source image unloadable
+    pub fn error(&self) -> Option<&LoadError> {
+        self.error.as_ref()
+    }
 
-        assert!(!self.full.is_loaded());
+    /// Mark the source image as unloadable due to `error`
+    pub fn mark_unloadable(&mut self, error: LoadError) {
+        self.error = Some(error);
     }
 
     /// Forget if the image has been marked unloadable
     pub fn forget_unloadable(&mut self) {
-        self.unloadable = false;
+        self.error = None;
+    }
+
+    /// Whether the image is loaded regardless of the decoding limits
+    pub fn ignores_limits(&self) -> bool {
+        self.ignore_limits
+    }
+
+    /// Load the image regardless of the decoding limits
+    pub fn set_ignore_limits(&mut self) {
+        self.ignore_limits = true;
+    }
+
+    /// The rating and tags of the image, if its metadata is loaded
+    pub fn tags(&self) -> Option<&ImageTags> {
+        self.metadata.loaded().map(|metadata| &metadata.tags)
+    }
+
+    pub fn is_marked(&self) -> bool {
+        self.marked
+    }
+
+    pub fn set_marked(&mut self, marked: bool) {
+        self.marked = marked;
+    }
+}
+
+impl LoadError {
+    pub fn new(kind: LoadErrorKind, message: String) -> Self {
+        Self { kind, message }
+    }
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl ImageSource {
+    /// The path of the source as provided to rlens
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::File(path) => path,
+            Self::Memory { path, .. } => path,
+        }
+    }
+
+    /// The path of the source's file if the data is read from the disk
+    pub fn file_path(&self) -> Option<&Path> {
+        match self {
+            Self::File(path) => Some(path),
+            Self::Memory { .. } => None,
+        }
+    }
+}
+
+impl Display for ImageSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.path().display())
+    }
+}
+
+// Avoid dumping the raw image data
+impl Debug for ImageSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::File(path) => f.debug_tuple("File").field(path).finish(),
+            Self::Memory { path, data } => f
+                .debug_struct("Memory")
+                .field("path", path)
+                .field("bytes", &data.len())
+                .finish(),
+        }
     }
 }
 
 impl<T> LoadState<T> {
     /// Get the loaded item
+    /// Stale items are included
     pub fn loaded(&self) -> Option<&T> {
         match self {
-            Self::Loaded(loaded) => Some(loaded),
+            Self::Loaded(loaded) | Self::Stale(loaded) => Some(loaded),
+            Self::Unloaded => None,
+        }
+    }
+
+    /// Get the loaded item mutably
+    /// Stale items are included
+    pub fn loaded_mut(&mut self) -> Option<&mut T> {
+        match self {
+            Self::Loaded(loaded) | Self::Stale(loaded) => Some(loaded),
             Self::Unloaded => None,
         }
     }
 
     fn take_loaded(self) -> Option<T> {
         match self {
-            Self::Loaded(loaded) => Some(loaded),
+            Self::Loaded(loaded) | Self::Stale(loaded) => Some(loaded),
             Self::Unloaded => None,
         }
     }
 
-    /// Check if the item is loaded
-    pub fn is_loaded(&self) -> bool {
+    /// Check if the item should be (re)loaded
+    /// True for unloaded and stale items
+    pub fn needs_load(&self) -> bool {
         match self {
-            Self::Loaded(_) => true,
-            Self::Unloaded => false,
+            Self::Loaded(_) => false,
+            Self::Unloaded | Self::Stale(_) => true,
         }
     }
 
@@ -107,13 +420,25 @@ impl<T> LoadState<T> {
         mem::replace(self, Self::Loaded(loaded)).take_loaded()
     }
 
-    /// Set an unloaded item as loaded
-    /// Panics if the item is already loaded
-    pub fn load(&mut self, loaded: T) {
+    /// Set an unloaded or stale item as loaded
+    /// Returns the stale item if present
+    /// Panics if the item is already loaded and not stale
+    pub fn load(&mut self, loaded: T) -> Option<T> {
         assert!(
-            self.set_loaded(loaded).is_none(),
+            !matches!(self, Self::Loaded(_)),
             "Loaded over a loaded item"
-        )
+        );
+        self.set_loaded(loaded)
+    }
+
+    /// Mark a loaded item as stale
+    /// No effect if the item is not loaded
+    pub fn mark_stale(&mut self) {
+        if let Self::Loaded(_) = self {
+            if let Some(loaded) = mem::replace(self, Self::Unloaded).take_loaded() {
+                *self = Self::Stale(loaded);
+            }
+        }
     }
 
     /// Unload the item and return the loaded item if possible
@@ -134,9 +459,11 @@ impl LoadState<LoadedImage> {
 impl LoadedImage {
     /// Register an image into the canvas
     /// * `image_data`: The image data in RGB8 pixels
+    /// * `hdr`: The high precision data the image was tone mapped from
     pub fn register(
         image_data: &[u8],
         dimentions: (u32, u32),
+        hdr: Option<HdrImage>,
         gfx: &mut Gfx,
     ) -> Result<Self, String> {
         let id = gfx
@@ -146,7 +473,7 @@ impl LoadedImage {
 
         let size = IntSize::from(dimentions).to_f32();
 
-        Ok(Self { id, size })
+        Ok(Self { id, size, hdr })
     }
 
     pub fn id(&self) -> ImageId {
@@ -157,6 +484,27 @@ impl LoadedImage {
         self.size
     }
 
+    /// Tone map the image again from its high precision data
+    /// Returns whether the image was updated
+    /// No effect if the high precision data was not retained, or the tone mapping is unchanged
+    pub fn set_tone_mapping(
+        &mut self,
+        tone_mapping: ToneMapping,
+        gfx: &mut Gfx,
+    ) -> Result<bool, String> {
+        let hdr = match &mut self.hdr {
+            Some(hdr) if hdr.tone_mapping() != tone_mapping => hdr,
+            _ => return Ok(false),
+        };
+
+        let image = hdr.render(tone_mapping);
+        gfx.canvas
+            .replace_image_data(self.id, &image, image.dimensions())
+            .map_err(|e| format!("Failed to update an image on the canvas: {}", e))?;
+
+        Ok(true)
+    }
+
     /// Unload the image
     pub fn unload(self, gfx: &mut Gfx) {
         gfx.canvas.delete_image(self.id);
@@ -1,19 +1,39 @@
 //! Module for loading images and their metadata from the disk, and the image loader thread
 
+use crate::color::{ColorTransform, TargetProfiles};
+use crate::external_loader::ExternalLoaders;
 use crate::gfx::Gfx;
-use crate::image::{LoadedImage, Metadata};
+use crate::image::{
+    ExifDetails, FileDetails, GpsLocation, ImageSource, LoadError, LoadErrorKind, LoadedImage,
+    Metadata,
+};
+use crate::image_transform::Orientation;
 use crate::load_request::{
-    FullRequest, ImageType, LoadRequest, LoadRequestResponse, ThumbnailRequest,
+    FullRequest, HashRequest, HashResponse, ImageType, LoadRequest, LoadRequestCommon,
+    LoadRequestResponse, SortRequest, SortResponse, ThumbnailRequest,
 };
+use crate::phash::ImageHash;
 use crate::program::{Request, RequestSender};
-use crate::util::{hash_filepath, PrintErr};
+use crate::rlens::sort_order;
+use crate::sidecar::read_tags;
+use crate::tone_map::{is_high_precision, HdrImage, ToneMapping};
+use crate::util::{hash_filepath, write_atomically, PrintErr};
 
-use image::{io::Reader as ImageReader, DynamicImage, ImageFormat};
-use std::fs::{self, File};
-use std::io::BufReader;
+use exif::{Exif, In, Tag, Value};
+use image::codecs::{jpeg::JpegDecoder, png::PngDecoder};
+use image::io::{Limits, Reader as ImageReader};
+use image::{DynamicImage, ImageDecoder, ImageError, ImageFormat};
+use qcms::Profile;
+use std::any::Any;
+use std::fs::{self, File, Permissions};
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom};
+use std::iter;
+use std::panic::{catch_unwind, AssertUnwindSafe};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc::{sync_channel, SyncSender};
-use std::thread::{spawn, JoinHandle};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{sync_channel, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::{sleep, spawn, JoinHandle};
+use std::time::{Duration, UNIX_EPOCH};
 
 /// Run the image loader thread
 ///
@@ -26,101 +46,282 @@ use std::thread::{spawn, JoinHandle};
 ///
 /// When the sender is dropped, the thread will exit, and so can be safely joined
 ///
+/// A panic while handling a request is reported as a load failure of the requested image, and
+/// if the loader ever dies regardless, it is restarted
+///
 pub fn run_image_loader(
     request_tx: RequestSender,
-    thumbnail_dir: PathBuf,
-    thumbnail_size: u32,
+    settings: LoaderSettings,
 ) -> (SyncSender<LoadRequest>, JoinHandle<()>) {
     let (load_request_tx, load_request_rx) = sync_channel::<LoadRequest>(0);
 
-    let thread = spawn(move || {
-        loop {
-            // Get a load request from the main thread
-            request_tx.send(Request::ImageLoaderReady).ok();
-            // Wait for the response, sleeping until loading is needed
-            let req = if let Ok(r) = load_request_rx.recv() {
-                // We have been sent a load request
-                r
-            } else {
-                // The program is exiting, so return to be joined
-                return;
-            };
-
-            // Handle the request
-            if let Some(resp) = req.handle(&thumbnail_dir, thumbnail_size) {
-                request_tx.send(Request::LoadImage(resp)).ok();
-            } else {
-                // The load failed so mark the source as unloadable
-                let index = req.index();
-                request_tx.send(Request::MarkUnloadable(index)).ok();
-            }
-
-            // Unload any out of range images
-            request_tx.send(Request::UnloadImages).ok();
+    let thread = spawn(move || loop {
+        let run = || run_loader(&request_tx, &load_request_rx, &settings);
+        match catch_unwind(AssertUnwindSafe(run)) {
+            // The program is exiting, so return to be joined
+            Ok(()) => return,
+            // The loader died, so restart it
+            Err(_) => eprintln!("Error: The image loader crashed, restarting"),
         }
     });
 
     (load_request_tx, thread)
 }
 
+/// The loop of the image loader
+/// Returns when the program is exiting
+fn run_loader(
+    request_tx: &RequestSender,
+    load_request_rx: &Receiver<LoadRequest>,
+    settings: &LoaderSettings,
+) {
+    loop {
+        // Get a load request from the main thread
+        request_tx.send(Request::ImageLoaderReady).ok();
+        // Wait for the response, sleeping until loading is needed
+        let req = if let Ok(r) = load_request_rx.recv() {
+            // We have been sent a load request
+            r
+        } else {
+            // The program is exiting
+            return;
+        };
+
+        // Handle the request, isolating any panics in the decoders
+        let result = catch_unwind(AssertUnwindSafe(|| req.handle(settings)))
+            .unwrap_or_else(|panic| Err(panic_error(&req, panic)));
+
+        match result {
+            // The image is requested again once the loader is ready, so wait for the running
+            // decode rather than retrying straight away
+            Err(e) if e.kind == LoadErrorKind::Busy => sleep(BUSY_RETRY_INTERVAL),
+            result => match result.print_err() {
+                Ok(resp) => {
+                    request_tx.send(resp).ok();
+                }
+                Err(e) => {
+                    // The load failed so mark the source as unloadable
+                    if let Some(details) = req.details() {
+                        request_tx
+                            .send(Request::MarkUnloadable(details.index, details.key, e))
+                            .ok();
+                    }
+                }
+            },
+        }
+
+        // Unload any out of range images
+        request_tx.send(Request::UnloadImages).ok();
+    }
+}
+
+/// Create the load error for a panic caught while handling `request`
+fn panic_error(request: &LoadRequest, panic: Box<dyn Any + Send>) -> LoadError {
+    let reason = panic
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| panic.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown cause".to_string());
+
+    let message = match request.details() {
+        Some(details) => format!(
+            "The loader panicked on image at `{}`: {}",
+            details.source, reason
+        ),
+        None => format!("The loader panicked while sorting: {}", reason),
+    };
+    LoadError::new(LoadErrorKind::Panic, message)
+}
+
+/// Settings of the image loader thread
+#[derive(Debug)]
+pub struct LoaderSettings {
+    /// Path of the thumbnail directory
+    pub thumbnail_dir: PathBuf,
+    /// The configured thumbnail size, the smallest that thumbnails are requested at
+    pub thumbnail_size: u32,
+    /// Commands to fall back on for images that cannot be decoded
+    pub external_loaders: ExternalLoaders,
+    /// Profiles to convert images into
+    pub color_profiles: TargetProfiles,
+    /// Limits on decoding images
+    pub limits: DecodeLimits,
+}
+
+/// Limits on decoding images, to guard against decompression bombs
+/// Each limit is unbounded if not set
+#[derive(Clone, Copy, Default, Debug)]
+pub struct DecodeLimits {
+    /// Maximum number of pixels in an image
+    pub max_pixels: Option<u64>,
+    /// Maximum number of bytes that may be allocated when decoding an image
+    pub max_alloc: Option<u64>,
+    /// Maximum time that decoding an image may take
+    pub timeout: Option<Duration>,
+}
+
+impl LoaderSettings {
+    /// Get the decoding limits that apply to a request
+    fn limits_for(&self, details: &LoadRequestCommon) -> Option<&DecodeLimits> {
+        (!details.ignore_limits).then_some(&self.limits)
+    }
+}
+
 impl LoadRequest {
     /// Handle a load request
-    fn handle(&self, thumbnail_dir: &Path, thumbnail_size: u32) -> Option<LoadRequestResponse> {
+    /// Returns the request to make to the main thread with the result
+    fn handle(&self, settings: &LoaderSettings) -> Result<Request, LoadError> {
+        let load_image = |resp| Request::LoadImage(Box::new(resp));
         match self {
-            LoadRequest::Full(details) => handle_full_request(details),
+            LoadRequest::Full(details) => handle_full_request(details, settings).map(load_image),
             LoadRequest::Thumbnail(details) => {
-                handle_thumbnail_request(details, thumbnail_dir, thumbnail_size)
+                handle_thumbnail_request(details, settings).map(load_image)
             }
+            LoadRequest::Hash(details) => {
+                handle_hash_request(details, settings).map(Request::SetHash)
+            }
+            LoadRequest::Sort(details) => Ok(Request::SortOrder(handle_sort_request(details))),
         }
     }
 }
 
-fn handle_full_request(request: &FullRequest) -> Option<LoadRequestResponse> {
-    // Load the full image
-    let image = load_full(&request.details.path);
+/// Read the values of the images that are not yet known, and order the images by them
+fn handle_sort_request(request: &SortRequest) -> SortResponse {
+    let values = request
+        .images
+        .iter()
+        .map(|image| {
+            let value = image
+                .value
+                .clone()
+                .or_else(|| request.key.read_value(&image.source));
+            (value, image.key)
+        })
+        .collect();
+
+    SortResponse {
+        order: sort_order(values, request.reverse),
+        generation: request.generation,
+    }
+}
+
+fn handle_full_request(
+    request: &FullRequest,
+    settings: &LoaderSettings,
+) -> Result<LoadRequestResponse, LoadError> {
+    // Load the full image, converting it for the display
+    let conversion = Conversion {
+        profile: request
+            .details
+            .color_manage
+            .then_some(&*settings.color_profiles.display),
+        tone_mapping: request.tone_mapping,
+    };
+    let image = load_full(
+        &request.details.source,
+        &settings.external_loaders,
+        settings.limits_for(&request.details),
+        conversion,
+    );
 
     image.map(|(image, metadata)| LoadRequestResponse {
         type_: ImageType::Full,
         index: request.details.index,
+        key: request.details.key,
         image,
         metadata,
     })
 }
 
 /// Load a full image
-fn load_full(path: &Path) -> Option<(Image, Metadata)> {
-    Image::load(path).print_err().ok()
+fn load_full(
+    source: &ImageSource,
+    external_loaders: &ExternalLoaders,
+    limits: Option<&DecodeLimits>,
+    conversion: Conversion,
+) -> Result<(Image, Metadata), LoadError> {
+    Image::load(source, external_loaders, limits, conversion)
 }
 
 fn handle_thumbnail_request(
     request: &ThumbnailRequest,
-    thumbnail_dir: &Path,
-    thumbnail_size: u32,
-) -> Option<LoadRequestResponse> {
+    settings: &LoaderSettings,
+) -> Result<LoadRequestResponse, LoadError> {
+    let LoaderSettings {
+        thumbnail_dir,
+        external_loaders,
+        color_profiles,
+        ..
+    } = settings;
+    let thumbnail_size = request.size;
+    let limits = settings.limits_for(&request.details);
+
+    // Generated thumbnails are converted to sRGB so that they are independent of the display
+    let conversion = Conversion {
+        profile: request
+            .details
+            .color_manage
+            .then_some(&*color_profiles.srgb),
+        tone_mapping: ToneMapping::default(),
+    };
+
+    let src_path = match request.details.source.file_path() {
+        Some(path) => path,
+        None => {
+            // In-memory sources are not cached, so always generate the thumbnail
+            let ThumbnailResult {
+                thumbnail,
+                metadata,
+                ..
+            } = generate_thumbnail(
+                &request.details.source,
+                thumbnail_size,
+                external_loaders,
+                limits,
+                conversion,
+            )?;
+
+            return Ok(LoadRequestResponse {
+                type_: ImageType::Thumbnail(thumbnail_size),
+                index: request.details.index,
+                key: request.details.key,
+                image: thumbnail,
+                metadata,
+            });
+        }
+    };
+
     // Get the canonical path of the source image
-    let src_path = request
-        .details
-        .path
-        .canonicalize()
-        .map_err(|e| {
+    let src_path = src_path.canonicalize().map_err(|e| {
+        LoadError::new(
+            LoadErrorKind::Read,
             format!(
                 "Error: Failed to obtain the canonical path of `{}`: {}",
-                request.details.path.display(),
+                src_path.display(),
                 e
-            )
-        })
-        .print_err()
-        .ok()?;
+            ),
+        )
+    })?;
 
     // Get the path for the thumbnail
-    let thumbnail_path = thumbnail_path(&src_path, thumbnail_dir);
+    let size_dir = (thumbnail_size != settings.thumbnail_size).then_some(thumbnail_size);
+    let thumbnail_path = thumbnail_path(&src_path, thumbnail_dir, size_dir);
 
     // Load / generate the thumbnail
     let thumbnail_result = {
         // Search for an existing thumbnail, and fallback to generating if not found
-        let existing = || load_existing_thumbnail(&thumbnail_path, &src_path);
-        let generated = || generate_thumbnail(&src_path, thumbnail_size);
-        existing().or_else(generated)
+        let existing = || load_existing_thumbnail(&thumbnail_path, &src_path, external_loaders);
+        let generated = || {
+            let source = ImageSource::File(src_path.clone());
+            generate_thumbnail(
+                &source,
+                thumbnail_size,
+                external_loaders,
+                limits,
+                conversion,
+            )
+        };
+        existing().map_or_else(generated, Ok)
     };
 
     thumbnail_result.map(
@@ -135,8 +336,9 @@ fn handle_thumbnail_request(
             }
 
             LoadRequestResponse {
-                type_: ImageType::Thumbnail,
+                type_: ImageType::Thumbnail(thumbnail_size),
                 index: request.details.index,
+                key: request.details.key,
                 image: thumbnail,
                 metadata,
             }
@@ -144,6 +346,75 @@ fn handle_thumbnail_request(
     )
 }
 
+fn handle_hash_request(
+    request: &HashRequest,
+    settings: &LoaderSettings,
+) -> Result<HashResponse, LoadError> {
+    let details = &request.thumbnail.details;
+    let response = |hash| HashResponse {
+        index: details.index,
+        key: details.key,
+        hash,
+    };
+
+    // The cache path of the hash, for sources on the disk
+    let paths = details
+        .source
+        .file_path()
+        .and_then(|path| path.canonicalize().ok())
+        .map(|src_path| {
+            let hash_path = hash_path(&src_path, &settings.thumbnail_dir);
+            (src_path, hash_path)
+        });
+
+    // Search for an existing hash
+    if let Some(hash) = paths
+        .as_ref()
+        .and_then(|(src_path, hash_path)| load_existing_hash(hash_path, src_path))
+    {
+        return Ok(response(hash));
+    }
+
+    // Compute the hash from the thumbnail
+    let thumbnail = handle_thumbnail_request(&request.thumbnail, settings)?;
+    let hash = ImageHash::compute(&thumbnail.image.image);
+
+    if let Some((_, hash_path)) = paths.filter(|_| request.thumbnail.save) {
+        write_atomically(&hash_path, |temp_path| {
+            fs::write(temp_path, hash.to_hex()).map_err(|e| e.to_string())
+        })
+        .print_err()
+        .ok();
+    }
+
+    Ok(response(hash))
+}
+
+/// Try to load an existing hash
+/// Fails if the hash cannot be read, or the source image has been modified since it was cached
+fn load_existing_hash(hash_path: &Path, src_path: &Path) -> Option<ImageHash> {
+    let stale = check_stale(hash_path, src_path).unwrap_or(false);
+    if stale {
+        return None;
+    }
+
+    ImageHash::from_hex(&fs::read_to_string(hash_path).ok()?)
+}
+
+/// Get the path for the cached hash of the image at `path`
+/// Hashes are kept in a subdirectory of the thumbnail directory
+/// Pre: `path` is absolute
+fn hash_path(path: &Path, thumbnail_dir: &Path) -> PathBuf {
+    assert!(path.is_absolute());
+
+    const HASH_DIR_NAME: &str = "hashes";
+    let mut hash_path = thumbnail_dir.to_path_buf();
+    hash_path.push(HASH_DIR_NAME);
+    hash_path.push(hash_filepath(path));
+
+    hash_path
+}
+
 /// The result of loading a thumbnail
 struct ThumbnailResult {
     thumbnail: Image,
@@ -156,20 +427,58 @@ struct ThumbnailResult {
 /// Try to load an existing thumbnail
 /// Fails if the thumbnail cannot be loaded, or the source image has been modified since the
 /// thumbnail's creation
-fn load_existing_thumbnail(thumbnail_path: &Path, src_path: &Path) -> Option<ThumbnailResult> {
+fn load_existing_thumbnail(
+    thumbnail_path: &Path,
+    src_path: &Path,
+    external_loaders: &ExternalLoaders,
+) -> Option<ThumbnailResult> {
     if thumbnail_path.exists() {
         // Fail if the thumbnail is stale
         // (Assume not stale if we cannot determine this)
-        let stale = check_stale_thumbnail(thumbnail_path, src_path).unwrap_or(false);
+        let stale = check_stale(thumbnail_path, src_path).unwrap_or(false);
         if stale {
             return None;
         }
 
-        // Try to load the thumbnail
-        let (thumbnail, _) = Image::load(thumbnail_path).print_err().ok()?;
+        // Try to load the thumbnail, discarding it if it is broken so that it is regenerated
+        let thumbnail_source = ImageSource::File(thumbnail_path.to_path_buf());
+        let thumbnail = if is_complete_png(thumbnail_path) {
+            Image::load(
+                &thumbnail_source,
+                &ExternalLoaders::none(),
+                None,
+                Conversion::none(),
+            )
+            .map(|(thumbnail, _)| thumbnail)
+            .map_err(|e| e.to_string())
+        } else {
+            Err(format!(
+                "Error: Incomplete thumbnail at `{}`",
+                thumbnail_path.display()
+            ))
+        };
+        let thumbnail = match thumbnail.print_err() {
+            Ok(thumbnail) => thumbnail,
+            Err(_) => {
+                fs::remove_file(thumbnail_path).ok();
+                return None;
+            }
+        };
 
         // Extract the metadata for the source image
-        let metadata = extract_metadata(src_path).print_err().ok()?;
+        // Images that only the external loaders can decode cannot be measured without loading
+        // them, so they take the dimensions of the thumbnail until they are loaded
+        let metadata = extract_metadata(&ImageSource::File(src_path.to_path_buf()))
+            .or_else(|e| {
+                if external_loaders.matches(src_path) {
+                    let dimensions = (thumbnail.image.width(), thumbnail.image.height());
+                    Ok(external_metadata(src_path, dimensions))
+                } else {
+                    Err(e)
+                }
+            })
+            .print_err()
+            .ok()?;
 
         Some(ThumbnailResult {
             thumbnail,
@@ -181,126 +490,671 @@ fn load_existing_thumbnail(thumbnail_path: &Path, src_path: &Path) -> Option<Thu
     }
 }
 
-/// Generate a thumbnail for the image at `path`
-fn generate_thumbnail(path: &Path, thumbnail_size: u32) -> Option<ThumbnailResult> {
-    let (src, metadata) = Image::load(path).print_err().ok()?;
+/// Generate a thumbnail for the image from `source`
+fn generate_thumbnail(
+    source: &ImageSource,
+    thumbnail_size: u32,
+    external_loaders: &ExternalLoaders,
+    limits: Option<&DecodeLimits>,
+    conversion: Conversion,
+) -> Result<ThumbnailResult, LoadError> {
+    let (src, metadata) = Image::load(source, external_loaders, limits, conversion)?;
     let thumbnail = src.generate_thumbnail(thumbnail_size);
-    Some(ThumbnailResult {
+    Ok(ThumbnailResult {
         thumbnail,
         metadata,
         generated: true,
     })
 }
 
-/// Get the thumbnail path for the image at `path`
+/// Move the cached thumbnails and hash of the image at `from` to the image at `to`
+/// Pre: `from` and `to` are absolute
+pub fn move_cached(from: &Path, to: &Path, thumbnail_dir: &Path) {
+    let cached = cached_sizes(thumbnail_dir)
+        .map(|size| {
+            (
+                thumbnail_path(from, thumbnail_dir, size),
+                thumbnail_path(to, thumbnail_dir, size),
+            )
+        })
+        .chain(iter::once((
+            hash_path(from, thumbnail_dir),
+            hash_path(to, thumbnail_dir),
+        )));
+
+    for (from, to) in cached {
+        if from.exists() {
+            fs::rename(&from, &to)
+                .map_err(|e| format!("Error moving cached file `{}`: {}", from.display(), e))
+                .print_err()
+                .ok();
+        }
+    }
+}
+
+/// Remove the cached thumbnails and hash of the image at `path`
 /// Pre: `path` is absolute
-fn thumbnail_path(path: &Path, thumbnail_dir: &Path) -> PathBuf {
+pub fn remove_cached(path: &Path, thumbnail_dir: &Path) {
+    let cached = cached_sizes(thumbnail_dir)
+        .map(|size| thumbnail_path(path, thumbnail_dir, size))
+        .chain(iter::once(hash_path(path, thumbnail_dir)));
+
+    for cached in cached {
+        fs::remove_file(cached).ok();
+    }
+}
+
+/// The size subdirectories in the thumbnail directory, with `None` for the directory itself
+/// (see `thumbnail_path`)
+fn cached_sizes(thumbnail_dir: &Path) -> impl Iterator<Item = Option<u32>> {
+    let sizes = fs::read_dir(thumbnail_dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse().ok())
+        .collect::<Vec<u32>>();
+
+    iter::once(None).chain(sizes.into_iter().map(Some))
+}
+
+/// Get the path for the thumbnail of the image at `path`
+/// Thumbnails of the configured size (`size` of `None`) are kept directly in the thumbnail
+/// directory, where they were before there were other sizes, and thumbnails of each other size
+/// are kept in their own subdirectory
+/// Pre: `path` is absolute
+fn thumbnail_path(path: &Path, thumbnail_dir: &Path, size: Option<u32>) -> PathBuf {
     assert!(path.is_absolute());
 
     let hash_str = hash_filepath(path);
 
     let mut path = thumbnail_dir.to_path_buf();
+    if let Some(size) = size {
+        path.push(size.to_string());
+    }
     path.push(hash_str);
     path.set_extension("png");
 
     path
 }
 
-/// Check if a thumbnail is stale (i.e. The source has been modified since the thumbnail's creation)
+/// Check if the file at `path` is a complete PNG (i.e. it was not truncated while being written)
+fn is_complete_png(path: &Path) -> bool {
+    const SIGNATURE: [u8; 8] = *b"\x89PNG\r\n\x1a\n";
+    /// The final chunk of every PNG
+    const IEND_CHUNK: [u8; 12] = *b"\0\0\0\0IEND\xae\x42\x60\x82";
+
+    let check = || -> io::Result<bool> {
+        let mut file = File::open(path)?;
+
+        let mut signature = [0; 8];
+        file.read_exact(&mut signature)?;
+
+        let mut end = [0; 12];
+        file.seek(SeekFrom::End(-(end.len() as i64)))?;
+        file.read_exact(&mut end)?;
+
+        Ok(signature == SIGNATURE && end == IEND_CHUNK)
+    };
+
+    check().unwrap_or(false)
+}
+
+/// Check if a cached file (e.g. a thumbnail) is stale
+/// (i.e. The source has been modified since the cached file's creation)
 /// Returns `None` if this could not be determined
-fn check_stale_thumbnail(thumbnail: &Path, src: &Path) -> Option<bool> {
-    let thumbnail_creation_time = fs::metadata(thumbnail).ok()?.created().ok()?;
+fn check_stale(cached: &Path, src: &Path) -> Option<bool> {
+    let cached_creation_time = fs::metadata(cached).ok()?.created().ok()?;
     let src_mod_time = fs::metadata(src).ok()?.modified().ok()?;
 
-    Some(src_mod_time.duration_since(thumbnail_creation_time).is_ok())
+    Some(src_mod_time.duration_since(cached_creation_time).is_ok())
 }
 
 // === Image loading ===
 
 /// A loaded image in memory
 #[derive(Debug)]
-pub struct Image(DynamicImage);
+pub struct Image {
+    /// The image data for display
+    image: DynamicImage,
+    /// The high precision data of the image, if it has more than 8 bits per channel
+    hdr: Option<Box<HdrImage>>,
+}
+
+/// How decoded images are converted for display
+#[derive(Clone, Copy)]
+struct Conversion<'a> {
+    /// The profile to convert images into from their embedded color profiles
+    profile: Option<&'a Profile>,
+    /// The tone mapping for high precision images
+    tone_mapping: ToneMapping,
+}
+
+impl Conversion<'_> {
+    /// No color management, and the default tone mapping
+    fn none() -> Self {
+        Self {
+            profile: None,
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+}
 
 impl Image {
-    /// Load an image and its metadata from a file
-    fn load(path: &Path) -> Result<(Self, Metadata), String> {
-        let reader = reader(path)?;
+    /// Load an image and its metadata from a source
+    /// If the image cannot be decoded, the matching external loaders are tried
+    /// The image is decoded within `limits` if provided
+    fn load(
+        source: &ImageSource,
+        external_loaders: &ExternalLoaders,
+        limits: Option<&DecodeLimits>,
+        conversion: Conversion,
+    ) -> Result<(Self, Metadata), LoadError> {
+        Self::decode(source, limits, conversion).or_else(|e| match source.file_path() {
+            Some(path) if e.kind == LoadErrorKind::Decode && external_loaders.matches(path) => {
+                Self::load_external(path, external_loaders, limits, conversion).map_err(
+                    |external_e| {
+                        let message = format!("{}\n{}", e, external_e.message);
+                        LoadError::new(external_e.kind, message)
+                    },
+                )
+            }
+            _ => Err(e),
+        })
+    }
+
+    /// Decode an image and its metadata from a source
+    fn decode(
+        source: &ImageSource,
+        limits: Option<&DecodeLimits>,
+        conversion: Conversion,
+    ) -> Result<(Self, Metadata), LoadError> {
+        let reader = reader(source, limits).map_err(|e| LoadError::new(LoadErrorKind::Read, e))?;
+
+        let image_format = reader.format();
+        let format = image_format.and_then(format_str);
+
+        if let Some(limits) = limits {
+            check_size(source, limits)?;
+        }
 
-        let format = reader.format().and_then(format_str);
+        let image = decode_image(source, reader, limits.and_then(|l| l.timeout))?;
 
-        let image = reader
-            .decode()
-            .map_err(|e| format!("Failed to decode image at `{}`: {}", path.display(), e))?;
+        // Find the conversion from the embedded color profile
+        let color_transform = conversion.profile.and_then(|target| {
+            image_format
+                .and_then(|format| icc_profile(source, format))
+                .map(|icc| ColorTransform::new(&icc, target))
+                .transpose()
+                .map_err(|e| format!("Failed to color manage `{}`: {}", source, e))
+                .print_err()
+                .ok()
+                .flatten()
+                .flatten()
+        });
 
         let dimensions = (image.width(), image.height());
 
-        let metadata = Metadata { dimensions, format };
+        let metadata = read_metadata(source, dimensions, format, true);
 
-        Ok((Self(image), metadata))
+        Ok((
+            Self::new(image, color_transform, conversion.tone_mapping),
+            metadata,
+        ))
+    }
+
+    /// Load an image and its metadata with the external loaders
+    /// Their output is decoded within `limits` if provided
+    fn load_external(
+        path: &Path,
+        external_loaders: &ExternalLoaders,
+        limits: Option<&DecodeLimits>,
+        conversion: Conversion,
+    ) -> Result<(Self, Metadata), LoadError> {
+        let image = external_loaders.load(path, limits)?;
+
+        let metadata = external_metadata(path, (image.width(), image.height()));
+
+        Ok((Self::new(image, None, conversion.tone_mapping), metadata))
+    }
+
+    /// Convert a decoded image for display
+    /// High precision images are tone mapped, and their original data is retained
+    fn new(
+        image: DynamicImage,
+        color_transform: Option<ColorTransform>,
+        tone_mapping: ToneMapping,
+    ) -> Self {
+        if is_high_precision(&image) {
+            let mut hdr = HdrImage::new(image, color_transform);
+            let image = DynamicImage::ImageRgba8(hdr.render(tone_mapping));
+            Self {
+                image,
+                hdr: Some(Box::new(hdr)),
+            }
+        } else {
+            let image = match color_transform {
+                Some(transform) => transform.apply(image),
+                None => image,
+            };
+            Self { image, hdr: None }
+        }
     }
 
     /// Generate a thumbnail of the image
     /// The thumbnail fits within (`thumbnail_size` x `thumbnail_size`) and preserves the original aspect ratio
     fn generate_thumbnail(&self, thumbnail_size: u32) -> Self {
-        Self(self.0.thumbnail(thumbnail_size, thumbnail_size))
+        Self {
+            image: self.image.thumbnail(thumbnail_size, thumbnail_size),
+            hdr: None,
+        }
     }
 
-    /// Save the image to the given path
+    /// Save the image to the given path as a PNG
+    /// The file at `path` is never partially written
     fn save(&self, path: &Path) -> Result<(), String> {
-        self.0
-            .save_with_format(path, ImageFormat::Png)
-            .map_err(|e| format!("Error: Failed to save image at `{}`: {}", path.display(), e))
+        write_atomically(path, |temp_path| {
+            self.image
+                .save_with_format(temp_path, ImageFormat::Png)
+                .map_err(|e| e.to_string())
+        })
     }
 
     /// Load the image into the canvas
     pub fn load_into_canvas(self, gfx: &mut Gfx) -> Result<LoadedImage, String> {
         // Convert to RGBA8
-        let image = self.0.into_rgba8();
+        let image = self.image.into_rgba8();
 
         let dimentions = image.dimensions();
 
         let image_data = image.into_vec();
 
-        LoadedImage::register(&image_data, dimentions, gfx)
+        LoadedImage::register(&image_data, dimentions, self.hdr.map(|hdr| *hdr), gfx)
     }
 }
 
-/// Create an image reader for the file at `path`
-fn reader(path: &Path) -> Result<ImageReader<BufReader<File>>, String> {
-    let read_err = |e| format!("Failed to read image at `{}`: {}", path.display(), e);
+/// Raw image data that can be decoded by an `ImageReader`
+trait ImageData: BufRead + Seek + Send {}
 
-    let mut reader = ImageReader::open(path)
-        .map_err(read_err)?
+impl<T: BufRead + Seek + Send> ImageData for T {}
+
+/// Open the data of `source`
+fn image_data(source: &ImageSource) -> Result<Box<dyn ImageData>, String> {
+    let data: Box<dyn ImageData> = match source {
+        ImageSource::File(path) => {
+            Box::new(BufReader::new(File::open(path).map_err(|e| {
+                format!("Failed to read image at `{}`: {}", source, e)
+            })?))
+        }
+        ImageSource::Memory { data, .. } => Box::new(Cursor::new(data.clone())),
+    };
+
+    Ok(data)
+}
+
+/// Create an image reader for the data of `source`
+/// The allocation limit of `limits` is applied if provided
+fn reader(
+    source: &ImageSource,
+    limits: Option<&DecodeLimits>,
+) -> Result<ImageReader<Box<dyn ImageData>>, String> {
+    let read_err = |e| format!("Failed to read image at `{}`: {}", source, e);
+
+    let data = image_data(source)?;
+
+    let mut reader = ImageReader::new(data)
         .with_guessed_format()
         .map_err(read_err)?;
-    reader.no_limits();
+
+    let mut reader_limits = Limits::no_limits();
+    reader_limits.max_alloc = limits.and_then(|l| l.max_alloc);
+    reader.limits(reader_limits);
 
     Ok(reader)
 }
 
-/// Extract the metadata about the image at the path
+/// Check that the image from `source` is within the pixel limit, and that its displayed image
+/// (4 bytes per pixel) is within the allocation limit
+fn check_size(source: &ImageSource, limits: &DecodeLimits) -> Result<(), LoadError> {
+    if limits.max_pixels.is_none() && limits.max_alloc.is_none() {
+        return Ok(());
+    }
+
+    let dimensions = reader(source, None)
+        .map_err(|e| LoadError::new(LoadErrorKind::Read, e))?
+        .into_dimensions()
+        .map_err(|e| decode_error(source, e))?;
+
+    check_dimensions(source, dimensions, limits)
+}
+
+/// Check that an image from `source` of the given dimensions is within `limits` (see `check_size`)
+pub fn check_dimensions(
+    source: &ImageSource,
+    (width, height): (u32, u32),
+    limits: &DecodeLimits,
+) -> Result<(), LoadError> {
+    let pixels = u64::from(width) * u64::from(height);
+    if let Some(max_pixels) = limits.max_pixels.filter(|&max| pixels > max) {
+        return Err(LoadError::new(
+            LoadErrorKind::Limit,
+            format!(
+                "Image at `{}` has {} pixels, which exceeds the limit of {}",
+                source, pixels, max_pixels
+            ),
+        ));
+    }
+
+    let bytes = pixels.saturating_mul(4);
+    if let Some(max_alloc) = limits.max_alloc.filter(|&max| bytes > max) {
+        return Err(LoadError::new(
+            LoadErrorKind::Limit,
+            format!(
+                "Image at `{}` needs at least {} bytes, which exceeds the limit of {}",
+                source, bytes, max_alloc
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Whether a decode with a time limit is running
+/// Decodes that exceed the limit keep running in the background, so this stops them from piling up
+static TIMED_DECODE_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// How long the loader waits before retrying a load refused while a timed decode is running
+const BUSY_RETRY_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Clears `TIMED_DECODE_RUNNING` when the decode finishes, even if it panics
+struct TimedDecodeGuard;
+
+impl Drop for TimedDecodeGuard {
+    fn drop(&mut self) {
+        TIMED_DECODE_RUNNING.store(false, Ordering::Release);
+    }
+}
+
+/// Decode the image from `reader`, giving up after `timeout` if provided
+fn decode_image(
+    source: &ImageSource,
+    reader: ImageReader<Box<dyn ImageData>>,
+    timeout: Option<Duration>,
+) -> Result<DynamicImage, LoadError> {
+    let timeout = match timeout {
+        Some(timeout) => timeout,
+        None => return reader.decode().map_err(|e| decode_error(source, e)),
+    };
+
+    // A previous decode that exceeded the limit is still running
+    // Images are loaded one at a time, so no other decode can be running
+    if TIMED_DECODE_RUNNING.swap(true, Ordering::AcqRel) {
+        return Err(LoadError::new(
+            LoadErrorKind::Busy,
+            format!(
+                "Decoding image at `{}` was deferred, as a decode that exceeded the time limit is still running",
+                source
+            ),
+        ));
+    }
+
+    // Decode on a separate thread so that we can stop waiting for it
+    // The decode cannot be interrupted, so the thread is left to finish in the background
+    let (tx, rx) = sync_channel(1);
+    spawn(move || {
+        let guard = TimedDecodeGuard;
+        let result = reader.decode();
+        // The next decode may start as soon as this one is received
+        drop(guard);
+        tx.send(result).ok()
+    });
+
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result.map_err(|e| decode_error(source, e)),
+        Err(RecvTimeoutError::Timeout) => Err(LoadError::new(
+            LoadErrorKind::Limit,
+            format!(
+                "Decoding image at `{}` exceeded the time limit of {}s",
+                source,
+                timeout.as_secs_f32()
+            ),
+        )),
+        Err(RecvTimeoutError::Disconnected) => Err(LoadError::new(
+            LoadErrorKind::Panic,
+            format!("The decoder panicked on image at `{}`", source),
+        )),
+    }
+}
+
+/// Create the load error for a failed decode
+/// Exceeding the allocation limit is reported as a limit error
+fn decode_error(source: &ImageSource, e: ImageError) -> LoadError {
+    match e {
+        ImageError::Limits(e) => LoadError::new(
+            LoadErrorKind::Limit,
+            format!("Image at `{}` exceeds the decoding limits: {}", source, e),
+        ),
+        e => LoadError::new(
+            LoadErrorKind::Decode,
+            format!("Failed to decode image at `{}`: {}", source, e),
+        ),
+    }
+}
+
+/// Read the ICC profile embedded in the image from `source`
+/// Only PNG and JPEG images are supported
+fn icc_profile(source: &ImageSource, format: ImageFormat) -> Option<Vec<u8>> {
+    let data = image_data(source).ok()?;
+
+    match format {
+        ImageFormat::Png => PngDecoder::new(data).ok()?.icc_profile(),
+        ImageFormat::Jpeg => JpegDecoder::new(data).ok()?.icc_profile(),
+        _ => None,
+    }
+}
+
+/// Extract the metadata about the image from the source
 /// This should be used when the image itself will not be loaded
-fn extract_metadata(path: &Path) -> Result<Metadata, String> {
-    let reader = reader(path)?;
+pub fn extract_metadata(source: &ImageSource) -> Result<Metadata, String> {
+    let reader = reader(source, None)?;
 
     let format = reader.format().and_then(format_str);
 
-    let dimensions = reader.into_dimensions().map_err(|e| {
-        format!(
-            "Failed to extract the dimensions of `{}`: {}",
-            path.display(),
-            e
-        )
-    })?;
+    let dimensions = reader
+        .into_dimensions()
+        .map_err(|e| format!("Failed to extract the dimensions of `{}`: {}", source, e))?;
+
+    Ok(read_metadata(source, dimensions, format, true))
+}
+
+// === Metadata ===
+
+/// Build the metadata of an image at `path` loaded by the external loaders
+fn external_metadata(path: &Path, dimensions: (u32, u32)) -> Metadata {
+    // Report the format as the extension of the source
+    let format = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .map(str::to_lowercase);
 
-    Ok(Metadata {
-        dimensions: dimensions,
+    // External loaders are expected to output upright images
+    let source = ImageSource::File(path.to_path_buf());
+    let mut metadata = read_metadata(&source, dimensions, format, false);
+    metadata.external = true;
+    metadata
+}
+
+/// Build the metadata of an image, reading its EXIF data and file details from `source`
+/// `orient` is whether the image should be oriented by its EXIF orientation
+fn read_metadata(
+    source: &ImageSource,
+    dimensions: (u32, u32),
+    format: Option<String>,
+    orient: bool,
+) -> Metadata {
+    let exif = read_exif(source);
+
+    // Images without an orientation are assumed to be upright
+    let orientation = exif
+        .as_ref()
+        .filter(|_| orient)
+        .and_then(|exif| exif_uint(exif, Tag::Orientation))
+        .map(Orientation::from_exif)
+        .unwrap_or_default();
+
+    Metadata {
+        dimensions,
         format,
+        orientation,
+        exif: exif.as_ref().map(exif_details),
+        file: source.file_path().and_then(file_details),
+        tags: source
+            .file_path()
+            .and_then(|path| read_tags(path).print_err().ok())
+            .unwrap_or_default(),
+        external: false,
+    }
+}
+
+/// Read the dimensions of the image from `source` without decoding it
+pub fn read_dimensions(source: &ImageSource) -> Option<(u32, u32)> {
+    reader(source, None).ok()?.into_dimensions().ok()
+}
+
+/// Read the date and time that the image from `source` was taken from its EXIF data
+pub fn read_date_taken(source: &ImageSource) -> Option<String> {
+    exif_details(&read_exif(source)?).date_taken
+}
+
+/// Read the EXIF data of the image from `source`
+fn read_exif(source: &ImageSource) -> Option<Exif> {
+    let mut data = image_data(source).ok()?;
+    exif::Reader::new().read_from_container(&mut data).ok()
+}
+
+/// Collect the details of interest from EXIF data
+fn exif_details(exif: &Exif) -> ExifDetails {
+    let gps = || {
+        let latitude = exif_degrees(exif, Tag::GPSLatitude, Tag::GPSLatitudeRef, b'S')?;
+        let longitude = exif_degrees(exif, Tag::GPSLongitude, Tag::GPSLongitudeRef, b'W')?;
+
+        // Reference of 1 denotes below sea level
+        let altitude = exif_rational(exif, Tag::GPSAltitude).map(|altitude| {
+            match exif_uint(exif, Tag::GPSAltitudeRef) {
+                Some(1) => -altitude,
+                _ => altitude,
+            }
+        });
+
+        Some(GpsLocation {
+            latitude,
+            longitude,
+            altitude,
+        })
+    };
+
+    ExifDetails {
+        make: exif_ascii(exif, Tag::Make),
+        model: exif_ascii(exif, Tag::Model),
+        lens: exif_ascii(exif, Tag::LensModel),
+        exposure_time: exif_display(exif, Tag::ExposureTime),
+        f_number: exif_rational(exif, Tag::FNumber),
+        iso: exif_uint(exif, Tag::PhotographicSensitivity),
+        focal_length: exif_rational(exif, Tag::FocalLength),
+        date_taken: exif_display(exif, Tag::DateTimeOriginal),
+        gps: gps(),
+    }
+}
+
+fn exif_field(exif: &Exif, tag: Tag) -> Option<&exif::Field> {
+    exif.get_field(tag, In::PRIMARY)
+}
+
+fn exif_uint(exif: &Exif, tag: Tag) -> Option<u32> {
+    exif_field(exif, tag)?.value.get_uint(0)
+}
+
+fn exif_rational(exif: &Exif, tag: Tag) -> Option<f64> {
+    match &exif_field(exif, tag)?.value {
+        Value::Rational(v) => v.first().map(|r| r.to_f64()),
+        _ => None,
+    }
+}
+
+fn exif_ascii(exif: &Exif, tag: Tag) -> Option<String> {
+    match &exif_field(exif, tag)?.value {
+        Value::Ascii(v) => v
+            .first()
+            .map(|s| String::from_utf8_lossy(s).trim().to_string())
+            .filter(|s| !s.is_empty()),
+        _ => None,
+    }
+}
+
+/// Get the display form of a field (e.g. "1/250" for an exposure time)
+fn exif_display(exif: &Exif, tag: Tag) -> Option<String> {
+    Some(exif_field(exif, tag)?.display_value().to_string())
+}
+
+/// Get a GPS coordinate in degrees from its (degrees, minutes, seconds) field and reference field
+/// `negative_ref` is the reference that denotes a negative coordinate (e.g. 'S')
+fn exif_degrees(exif: &Exif, tag: Tag, ref_tag: Tag, negative_ref: u8) -> Option<f64> {
+    let degrees = match &exif_field(exif, tag)?.value {
+        Value::Rational(v) if v.len() >= 3 => {
+            v[0].to_f64() + v[1].to_f64() / 60.0 + v[2].to_f64() / 3600.0
+        }
+        _ => return None,
+    };
+
+    let negative = exif_ascii(exif, ref_tag)
+        .map(|r| r.as_bytes().first() == Some(&negative_ref))
+        .unwrap_or(false);
+
+    Some(if negative { -degrees } else { degrees })
+}
+
+/// Read the details of the file at `path`
+pub fn file_details(path: &Path) -> Option<FileDetails> {
+    let metadata = fs::metadata(path).ok()?;
+
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64);
+
+    Some(FileDetails {
+        size: metadata.len(),
+        modified,
+        permissions: permissions_str(&metadata.permissions()),
     })
 }
 
+/// Get a string representation of file permissions
+/// e.g. "rw-r--r--"
+#[cfg(unix)]
+fn permissions_str(permissions: &Permissions) -> String {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = permissions.mode();
+    "rwxrwxrwx"
+        .chars()
+        .enumerate()
+        .map(|(i, c)| if mode & (1 << (8 - i)) != 0 { c } else { '-' })
+        .collect()
+}
+
+/// Get a string representation of file permissions
+/// e.g. "rw-"
+#[cfg(not(unix))]
+fn permissions_str(permissions: &Permissions) -> String {
+    if permissions.readonly() {
+        "r--".to_string()
+    } else {
+        "rw-".to_string()
+    }
+}
+
 /// Get a string representation for an image format
 /// e.g. "png"
-fn format_str(format: ImageFormat) -> Option<&'static str> {
-    format.extensions_str().first().cloned()
+fn format_str(format: ImageFormat) -> Option<String> {
+    format.extensions_str().first().map(|s| s.to_string())
 }
@@ -8,6 +8,9 @@
 
 use crate::geometry::*;
 
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
 /// A transform on a raw image
 #[derive(Default)]
 pub struct ImageTransform {
@@ -22,7 +25,8 @@ pub struct ImageTransform {
 }
 
 /// A scaling mode based on the sizes of the image and view
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
 pub enum Scaling {
     /// No scaling performed (zoom remains at 1)
     #[default]
@@ -32,16 +36,23 @@ pub enum Scaling {
     /// Fit the height of the image to the height of the window
     FitHeight,
     /// Fit the dimensions of the image within the dimensions of the window (leads to black bars)
+    #[serde(rename = "fit")]
     FitImage,
 }
 
-#[derive(Default, Clone, Copy, Debug)]
+/// The orientation of an image's pixel data, as given by the EXIF Orientation tag (1-8)
+/// This is applied beneath the image transform so that the image is displayed upright
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Orientation(u32);
+
+#[derive(Default, Clone, Copy, Serialize, Deserialize, Debug)]
 pub struct Align {
     pub x: AlignX,
     pub y: AlignY,
 }
 
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
 pub enum AlignX {
     #[default]
     Left,
@@ -49,7 +60,8 @@ pub enum AlignX {
     Right,
 }
 
-#[derive(Default, Clone, Copy, Debug)]
+#[derive(Default, Clone, Copy, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
 pub enum AlignY {
     #[default]
     Top,
@@ -57,6 +69,111 @@ pub enum AlignY {
     Bottom,
 }
 
+impl Default for Orientation {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+impl Orientation {
+    /// Interpret the value of an EXIF Orientation tag
+    /// Invalid values are treated as the default orientation
+    pub fn from_exif(value: u32) -> Self {
+        if (1..=8).contains(&value) {
+            Self(value)
+        } else {
+            Self::default()
+        }
+    }
+
+    /// The value of the equivalent EXIF Orientation tag
+    pub fn exif(&self) -> u32 {
+        self.0
+    }
+
+    /// The orientation of a clockwise rotation applied after a horizontal flip
+    /// `None` if the rotation is not a multiple of 90 degrees
+    fn from_parts(rotation: f32, flip: bool) -> Option<Self> {
+        let quarters = rotation / 90.0;
+        if (quarters - quarters.round()).abs() > 1e-3 {
+            return None;
+        }
+
+        let value = match ((quarters.round() as i32).rem_euclid(4), flip) {
+            (0, false) => 1,
+            (0, true) => 2,
+            (2, false) => 3,
+            (2, true) => 4,
+            (3, true) => 5,
+            (1, false) => 6,
+            (1, true) => 7,
+            _ => 8,
+        };
+        Some(Self(value))
+    }
+
+    /// The orientation once the rotation and flip of `transform` are applied on top
+    /// `None` if the rotation of `transform` is not a multiple of 90 degrees
+    pub fn then_transform(&self, transform: &ImageTransform) -> Option<Self> {
+        let rotation = self.rotation() + transform.rotation;
+
+        // A flip on top reverses the rotation beneath it
+        let rotation = if transform.flip { -rotation } else { rotation };
+        Self::from_parts(rotation, self.flip() != transform.flip)
+    }
+
+    /// Apply the orientation to the pixel data of an image
+    pub fn apply(&self, image: DynamicImage) -> DynamicImage {
+        let image = if self.flip() { image.fliph() } else { image };
+        match self.rotation() as u32 {
+            90 => image.rotate90(),
+            180 => image.rotate180(),
+            270 => image.rotate270(),
+            _ => image,
+        }
+    }
+
+    /// Angle of clockwise rotation that is applied after the flip
+    fn rotation(&self) -> f32 {
+        match self.0 {
+            3 | 4 => 180.0,
+            6 | 7 => 90.0,
+            5 | 8 => 270.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Whether the image is flipped horizontally
+    fn flip(&self) -> bool {
+        matches!(self.0, 2 | 4 | 5 | 7)
+    }
+
+    /// The size of an image of size `size` once oriented
+    pub fn oriented_size(&self, size: Size) -> Size {
+        if self.rotation() % 180.0 == 0.0 {
+            size
+        } else {
+            Size::new(size.height, size.width)
+        }
+    }
+
+    /// Generate the transform that orients an image of size `size`
+    /// The oriented image has its top-left corner at the origin
+    pub fn transform(&self, size: Size) -> Transform {
+        let flip = if self.flip() {
+            Transform::scale(-1.0, 1.0)
+        } else {
+            Transform::identity()
+        };
+        let rotation = Transform::rotation(Angle::degrees(self.rotation()));
+        let orient = flip.then(&rotation);
+
+        // Translate the oriented image back to the origin
+        let bounds = orient.outer_transformed_box(&Rect::from_size(size));
+        orient.then_translate(-bounds.min.to_vector())
+    }
+}
+
 impl ImageTransform {
     /// Generate an initial transform based on scaling and align options
     pub fn initial(scaling: Scaling, align: Align, image_size: Size, view: Size) -> Self {
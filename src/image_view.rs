@@ -3,9 +3,10 @@
 use crate::geometry::*;
 use crate::gfx::{CanvasExt, Gfx};
 use crate::image::{Image, LoadedImage};
-use crate::image_transform::{Align, ImageTransform, Scaling};
+use crate::image_transform::{Align, ImageTransform, Orientation, Scaling};
 
 use femtovg::Color;
+use std::mem;
 
 pub struct ImageView {
     /// Index of the current image
@@ -15,10 +16,14 @@ pub struct ImageView {
     /// The transform on the current image
     /// `None` only if the current image is not loaded
     transform: Option<ImageTransform>,
+    /// A transform to use instead of the initial transform once the current image is loaded
+    restored_transform: Option<ImageTransform>,
     /// Initial scaling
     scaling: Scaling,
     /// Initial align
     align: Align,
+    /// Whether images are oriented by their EXIF orientation
+    auto_orient: bool,
 }
 
 impl ImageView {
@@ -26,8 +31,10 @@ impl ImageView {
         Self {
             current_image: index,
             transform: None,
+            restored_transform: None,
             scaling: Scaling::default(),
             align: Align::default(),
+            auto_orient: true,
         }
     }
 }
@@ -44,16 +51,86 @@ impl ImageView {
         images[self.current_image].full.loaded()
     }
 
+    /// Get the orientation of the current image
+    pub fn current_orientation(&self, images: &[Image]) -> Orientation {
+        if self.auto_orient {
+            images[self.current_image].orientation()
+        } else {
+            Orientation::default()
+        }
+    }
+
     /// Set the current image by index
     /// Pre: `index` is valid
     pub fn set_image(&mut self, index: usize, images: &[Image], view: Size) {
         // Update the index
         self.current_image = index;
+        self.restored_transform = None;
 
         // Reset if the new image is loaded
         self.reset_if_loaded(images, view);
     }
 
+    /// Update the current image after the image at `index` was removed from the image list
+    /// If the current image was removed, the image that takes its place becomes current
+    /// Pre: `images` is non-empty
+    pub fn on_remove(&mut self, index: usize, images: &[Image], view: Size) {
+        if self.current_image > index {
+            // Follow the current image to its new index
+            self.current_image -= 1;
+        } else if self.current_image == index {
+            let index = index.min(images.len() - 1);
+            self.set_image(index, images, view);
+        }
+    }
+
+    /// Update the current image after the image list was rebuilt
+    /// `same` is whether the image at `index` is the previous current image, in which case its
+    /// transform is kept
+    pub fn on_rebuild(&mut self, index: usize, same: bool, images: &[Image], view: Size) {
+        if same {
+            self.current_image = index;
+        } else {
+            self.set_image(index, images, view);
+        }
+    }
+
+    /// Set the current image with a transform restored from a session
+    /// The transform is used once the image is loaded
+    /// Pre: `index` is valid
+    pub fn restore(
+        &mut self,
+        index: usize,
+        transform: Option<ImageTransform>,
+        images: &[Image],
+        view: Size,
+    ) {
+        self.set_image(index, images, view);
+
+        if let Some(transform) = transform {
+            if self.transform.is_some() {
+                self.transform = Some(transform);
+            } else {
+                self.restored_transform = Some(transform);
+            }
+        }
+    }
+
+    /// Exchange the current image and transform with another view, such as when switching
+    /// between image lists
+    /// The settings of the views are kept
+    /// Pre: `images` is the image list of `other`
+    pub fn swap_state(&mut self, other: &mut Self, images: &[Image], view: Size) {
+        mem::swap(&mut self.current_image, &mut other.current_image);
+        mem::swap(&mut self.transform, &mut other.transform);
+        mem::swap(&mut self.restored_transform, &mut other.restored_transform);
+
+        // The image may have loaded before the view was shown
+        if self.transform.is_none() {
+            self.reset_if_loaded(images, view);
+        }
+    }
+
     /// The current transform
     pub fn transform(&mut self) -> Option<&mut ImageTransform> {
         self.transform.as_mut()
@@ -69,26 +146,34 @@ impl ImageView {
         &mut self.align
     }
 
+    /// Set whether images are oriented by their EXIF orientation
+    /// The transform is reset if the setting changes
+    pub fn set_auto_orient(&mut self, auto_orient: bool, images: &[Image], view: Size) {
+        if self.auto_orient != auto_orient {
+            self.auto_orient = auto_orient;
+            self.reset_if_loaded(images, view);
+        }
+    }
+
     /// Reset the image transform if the current image is loaded
     /// This should be called when the current image changes
     pub fn reset_if_loaded(&mut self, images: &[Image], view: Size) {
         if let Some(loaded_image) = self.current_loaded_image(images) {
-            self.reset_with_size(loaded_image.size(), view)
+            let orientation = self.current_orientation(images);
+            self.reset_with_size(orientation.oriented_size(loaded_image.size()), view)
         } else {
             // The current image is unloaded, so we have no transform
             self.transform = None;
         }
     }
 
-    /// Reset the image transform for an image of the given size
-    /// This should be called directly when the current image is loaded
-    pub fn reset_with_size(&mut self, image_size: Size, view: Size) {
-        self.transform = Some(ImageTransform::initial(
-            self.scaling,
-            self.align,
-            image_size,
-            view,
-        ));
+    /// Reset the image transform for an oriented image of the given size
+    fn reset_with_size(&mut self, image_size: Size, view: Size) {
+        let transform = match self.restored_transform.take() {
+            Some(transform) => transform,
+            None => ImageTransform::initial(self.scaling, self.align, image_size, view),
+        };
+        self.transform = Some(transform);
     }
 }
 
@@ -98,25 +183,34 @@ impl ImageView {
     /// Draw the image view if the current image is loaded
     pub fn draw(&self, images: &[Image], backdrop_color: Color, gfx: &mut Gfx) {
         if let Some(loaded_image) = self.current_loaded_image(images) {
-            self.draw_image(loaded_image, backdrop_color, gfx);
+            let orientation = self.current_orientation(images);
+            self.draw_image(loaded_image, orientation, backdrop_color, gfx);
         }
     }
 
     /// Draw the image view with the given image
     /// Pre: `image` is the current image
-    fn draw_image(&self, image: &LoadedImage, backdrop_color: Color, gfx: &mut Gfx) {
+    fn draw_image(
+        &self,
+        image: &LoadedImage,
+        orientation: Orientation,
+        backdrop_color: Color,
+        gfx: &mut Gfx,
+    ) {
         let canvas = &mut gfx.canvas;
 
         let id = image.id();
         let bounds = Rect::from_size(image.size());
 
-        // Get the transform
+        // Get the transform, beneath which the image is oriented
         // The current image is loaded so the transform is present
-        let transform = self
-            .transform
-            .as_ref()
-            .expect("Transform was not present when drawing the loaded image")
-            .transform();
+        let transform = orientation.transform(image.size()).then(
+            &self
+                .transform
+                .as_ref()
+                .expect("Transform was not present when drawing the loaded image")
+                .transform(),
+        );
 
         canvas.save_with(|canvas| {
             // Apply the current transform to the canvas
@@ -1,15 +1,18 @@
 //! Module for the details of load requests and their responses
 
-use crate::image::{Image, Metadata};
+use crate::image::{Image, ImageKey, ImageSource, Metadata};
 use crate::image_loader;
-
-use std::path::PathBuf;
+use crate::phash::ImageHash;
+use crate::rlens::{SortKey, SortValue};
+use crate::tone_map::ToneMapping;
 
 /// A request to load an image
 #[derive(Debug)]
 pub enum LoadRequest {
     Full(FullRequest),
     Thumbnail(ThumbnailRequest),
+    Hash(HashRequest),
+    Sort(SortRequest),
 }
 
 /// Common details of a load request
@@ -17,32 +20,91 @@ pub enum LoadRequest {
 pub struct LoadRequestCommon {
     /// Index of the image in the image list
     pub index: usize,
-    /// The path of the source image
+    /// Key of the image
+    pub key: ImageKey,
+    /// The source of the image
     //  Owning this greatly simplifies the image loader
-    pub path: PathBuf,
+    pub source: ImageSource,
+    /// Whether the image should be converted from its embedded color profile
+    pub color_manage: bool,
+    /// Whether the image should be loaded regardless of the decoding limits
+    pub ignore_limits: bool,
 }
 
 /// A request to load a full image
 #[derive(Debug)]
 pub struct FullRequest {
     pub details: LoadRequestCommon,
+    /// The tone mapping for high precision images
+    pub tone_mapping: ToneMapping,
 }
 
 /// A request to load a thumbnail
 #[derive(Debug)]
 pub struct ThumbnailRequest {
     pub details: LoadRequestCommon,
+    /// The size of the thumbnail (fit within 'size x size')
+    pub size: u32,
     /// Whether the thumbnail should saved if generated
     pub save: bool,
 }
 
+/// A request to compute the perceptual hash of an image
+#[derive(Debug)]
+pub struct HashRequest {
+    /// The thumbnail that the hash is computed from
+    pub thumbnail: ThumbnailRequest,
+}
+
+/// A request to read the values of the images for a sort key, and order them by those values
+#[derive(Debug)]
+pub struct SortRequest {
+    pub key: SortKey,
+    pub reverse: bool,
+    /// The images in their current order
+    pub images: Vec<SortImage>,
+    /// Identifies the sort, so that sorts that have been superseded are ignored
+    pub generation: u64,
+}
+
+/// An image to sort
+#[derive(Debug)]
+pub struct SortImage {
+    pub key: ImageKey,
+    pub source: ImageSource,
+    /// The value of the image, if it is already known
+    pub value: Option<SortValue>,
+}
+
+/// The response to a sort request
+#[derive(Debug)]
+pub struct SortResponse {
+    /// The keys of the images in sorted order
+    pub order: Vec<ImageKey>,
+    /// The generation of the request
+    pub generation: u64,
+}
+
+/// A successful response to a hash request
+#[derive(Debug)]
+pub struct HashResponse {
+    /// Index of the image in the image list when the request was made
+    pub index: usize,
+    /// Key of the image
+    pub key: ImageKey,
+    /// The hash of the image
+    pub hash: ImageHash,
+}
+
 /// A successful response to a load request
 #[derive(Debug)]
 pub struct LoadRequestResponse {
     /// The type of image loaded
     pub type_: ImageType,
-    /// Index of the image in the image list
+    /// Index of the image in the image list when the request was made
     pub index: usize,
+    /// Key of the image
+    pub key: ImageKey,
     /// The image data
     pub image: image_loader::Image,
     /// The metadata of the source image
@@ -52,42 +114,61 @@ pub struct LoadRequestResponse {
 #[derive(PartialEq, Debug)]
 pub enum ImageType {
     Full,
-    Thumbnail,
+    /// A thumbnail of the given size
+    Thumbnail(u32),
 }
 
 impl LoadRequestCommon {
-    pub fn for_image(index: usize, image: &Image) -> Self {
+    pub fn for_image(index: usize, image: &Image, color_manage: bool) -> Self {
         Self {
             index,
-            path: image.path().to_path_buf(),
+            key: image.key(),
+            source: image.source().clone(),
+            color_manage,
+            ignore_limits: image.ignores_limits(),
         }
     }
 }
 
 impl FullRequest {
-    pub fn for_image(index: usize, image: &Image) -> Self {
+    pub fn for_image(
+        index: usize,
+        image: &Image,
+        color_manage: bool,
+        tone_mapping: ToneMapping,
+    ) -> Self {
         Self {
-            details: LoadRequestCommon::for_image(index, image),
+            details: LoadRequestCommon::for_image(index, image, color_manage),
+            tone_mapping,
         }
     }
 }
 
 impl ThumbnailRequest {
-    pub fn for_image(index: usize, image: &Image, save: bool) -> Self {
+    pub fn for_image(
+        index: usize,
+        image: &Image,
+        size: u32,
+        save: bool,
+        color_manage: bool,
+    ) -> Self {
         Self {
-            details: LoadRequestCommon::for_image(index, image),
+            details: LoadRequestCommon::for_image(index, image, color_manage),
+            size,
             save,
         }
     }
 }
 
 impl LoadRequest {
-    /// Get the index associated with the request
-    pub fn index(&self) -> usize {
-        let details = match self {
-            Self::Full(req) => &req.details,
-            Self::Thumbnail(req) => &req.details,
-        };
-        details.index
+    /// Get the common details of the request
+    /// `None` for sorts, which do not load a single image
+    pub fn details(&self) -> Option<&LoadRequestCommon> {
+        match self {
+            Self::Full(req) => Some(&req.details),
+            Self::Thumbnail(req) => Some(&req.details),
+            Self::Hash(req) => Some(&req.thumbnail.details),
+            Self::Sort(_) => None,
+        }
     }
 }
@@ -4,15 +4,18 @@ use crate::command::{self, run_command, Command, CommandError};
 use crate::input::Key;
 use crate::keybinds::KeyBinds;
 use crate::program::RequestSender;
-use crate::rlens::{Mode, MODES};
+use crate::rlens::{Mode, SortKey, StackRule, MODES};
 use crate::util::StrError;
 
 pub use rlua::prelude::LuaResult;
 use rlua::{
     Context, FromLua, FromLuaMulti, Function, RegistryKey, Result, Table, ToLua, ToLuaMulti, Value,
 };
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
 use std::fs::read_to_string;
-use std::path::Path;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
 use std::result::Result as StdResult;
 use std::sync::{Arc, Mutex};
 
@@ -22,6 +25,8 @@ pub struct Lua {
     lua: rlua::Lua,
     /// Registered keybinds
     keybinds: Arc<Mutex<KeyBinds>>,
+    /// Sender for requests to the main thread
+    request_tx: RequestSender,
 }
 
 /// The lua API for rlens
@@ -50,10 +55,11 @@ impl Lua {
         let lua = Self {
             lua: rlua::Lua::new(),
             keybinds: Arc::new(Mutex::new(KeyBinds::new())),
+            request_tx,
         };
 
         lua.context(|ctx| {
-            ctx.load_api(request_tx, &lua.keybinds)
+            ctx.load_api(lua.request_tx.clone(), &lua.keybinds)
                 .map_err(|lua_err| format!("Error initialising the lua api: `{}`", lua_err))?;
 
             // Set config flags
@@ -100,6 +106,17 @@ impl Lua {
         })
     }
 
+    /// Restore the session saved at `path`
+    pub fn load_session(&self, path: &Path) -> Result<()> {
+        self.context(|ctx| {
+            run_command(
+                command::LoadSession(path.to_path_buf()),
+                &self.request_tx,
+                ctx,
+            )
+        })
+    }
+
     /// Run the lua RC at `rc_path`
     pub fn run_rc(&self, rc_path: &Path) -> StdResult<(), String> {
         let rc = read_to_string(&rc_path)
@@ -240,6 +257,15 @@ impl<'lua> LuaContext<'lua> {
         )?;
 
         self.load_rlens("reload", wrap_nullary_command(|| command::Reload, &tx))?;
+        self.load_rlens("ignore_limits", wrap_command(command::IgnoreLimits, &tx))?;
+
+        self.load_rlens("watch", wrap_command(command::Watch, &tx))?;
+        self.load_rlens(
+            "watch_directories",
+            wrap_command(command::WatchDirectories, &tx),
+        )?;
+
+        self.load_rlens("auto_orient", wrap_command(command::AutoOrient, &tx))?;
 
         self.load_rlens(
             "preload_range",
@@ -253,6 +279,142 @@ impl<'lua> LuaContext<'lua> {
             "save_thumbnails",
             wrap_command(command::SaveThumbnails, &tx),
         )?;
+        self.load_rlens(
+            "add_image",
+            wrap_command(
+                |(path, pos): (String, Option<usize>)| command::AddImage(path.into(), pos),
+                &tx,
+            ),
+        )?;
+        self.load_rlens("remove_image", wrap_command(command::RemoveImage, &tx))?;
+        self.load_rlens(
+            "set_image_list",
+            wrap_command(
+                |paths: Vec<String>| {
+                    command::SetImageList(paths.into_iter().map(PathBuf::from).collect())
+                },
+                &tx,
+            ),
+        )?;
+        self.load_rlens(
+            "save_orientation",
+            wrap_nullary_command(|| command::SaveOrientation, &tx),
+        )?;
+        self.load_rlens("trash", wrap_command(command::TrashImage, &tx))?;
+        self.load_rlens(
+            "move",
+            wrap_command(
+                |(i, dir): (usize, String)| command::MoveImage(i, dir.into()),
+                &tx,
+            ),
+        )?;
+        self.load_rlens(
+            "copy",
+            wrap_command(
+                |(i, dir): (usize, String)| command::CopyImage(i, dir.into()),
+                &tx,
+            ),
+        )?;
+        self.load_rlens(
+            "rename",
+            wrap_command(|(i, name)| command::RenameImage(i, name), &tx),
+        )?;
+        self.load_rlens(
+            "new_collection",
+            wrap_command(
+                |paths: Vec<String>| {
+                    command::NewCollection(paths.into_iter().map(PathBuf::from).collect())
+                },
+                &tx,
+            ),
+        )?;
+        self.load_rlens(
+            "collection",
+            wrap_nullary_command(|| command::ActiveCollection, &tx),
+        )?;
+        self.load_rlens(
+            "total_collections",
+            wrap_nullary_command(|| command::TotalCollections, &tx),
+        )?;
+        self.load_rlens(
+            "switch_collection",
+            wrap_command(command::SwitchCollection, &tx),
+        )?;
+        self.load_rlens(
+            "close_collection",
+            wrap_command(command::CloseCollection, &tx),
+        )?;
+        self.load_rlens("mark", wrap_command(|i| command::Mark(i, true), &tx))?;
+        self.load_rlens("unmark", wrap_command(|i| command::Mark(i, false), &tx))?;
+        self.load_rlens("toggle_mark", wrap_command(command::ToggleMark, &tx))?;
+        self.load_rlens("marked", wrap_nullary_command(|| command::Marked, &tx))?;
+        self.load_rlens(
+            "set_rating",
+            wrap_command(|(i, rating)| command::SetRating(i, rating), &tx),
+        )?;
+        self.load_rlens("tags", wrap_command(command::Tags, &tx))?;
+        self.load_rlens(
+            "set_tags",
+            wrap_command(|(i, tags)| command::SetTags(i, tags), &tx),
+        )?;
+        self.load_rlens(
+            "save_session",
+            wrap_command(|path: String| command::SaveSession(path.into()), &tx),
+        )?;
+        self.load_rlens(
+            "load_session",
+            wrap_command(|path: String| command::LoadSession(path.into()), &tx),
+        )?;
+        self.load_rlens("history", wrap_nullary_command(|| command::History, &tx))?;
+        self.load_rlens(
+            "export_list",
+            wrap_command(
+                |(path, marked_only): (String, Option<bool>)| {
+                    command::ExportList(path.into(), marked_only.unwrap_or(false))
+                },
+                &tx,
+            ),
+        )?;
+        self.load_rlens("filter", filter(&tx))?;
+        self.load_rlens(
+            "clear_filter",
+            wrap_nullary_command(|| command::ClearFilter, &tx),
+        )?;
+        self.load_rlens("absolute_index", wrap_command(command::AbsoluteIndex, &tx))?;
+        self.load_rlens("sort", sort(&tx))?;
+        self.load_rlens("stack", stack(&tx))?;
+        self.load_rlens("unstack", wrap_nullary_command(|| command::Unstack, &tx))?;
+        self.load_rlens(
+            "stack_members",
+            wrap_nullary_command(|| command::StackMembers, &tx),
+        )?;
+        self.load_rlens(
+            "stack_next",
+            wrap_nullary_command(|| command::StackNext, &tx),
+        )?;
+
+        self.load_rlens("hash_images", wrap_command(command::HashImages, &tx))?;
+        self.load_rlens(
+            "similar",
+            wrap_command(|(i, threshold)| command::Similar(i, threshold), &tx),
+        )?;
+        self.load_rlens(
+            "duplicate_groups",
+            wrap_command(command::DuplicateGroups, &tx),
+        )?;
+
+        self.load_rlens(
+            "color_management",
+            wrap_command(command::ColorManagement, &tx),
+        )?;
+        self.load_rlens(
+            "toggle_color_management",
+            wrap_nullary_command(|| command::ToggleColorManagement, &tx),
+        )?;
+
+        self.load_rlens("exposure", wrap_command(command::Exposure, &tx))?;
+        self.load_rlens("gamma", wrap_command(command::Gamma, &tx))?;
+        self.load_rlens("tone_map", wrap_command(command::ToneMap, &tx))?;
 
         self.load_rlens(
             "gallery_tile_width",
@@ -296,6 +458,22 @@ impl<'lua> LuaContext<'lua> {
             "gallery_border_color",
             wrap_command(command::GalleryBorderColor, &tx),
         )?;
+        self.load_rlens(
+            "gallery_error_color",
+            wrap_command(command::GalleryErrorColor, &tx),
+        )?;
+        self.load_rlens(
+            "gallery_badge_color",
+            wrap_command(command::GalleryBadgeColor, &tx),
+        )?;
+        self.load_rlens(
+            "gallery_mark_color",
+            wrap_command(command::GalleryMarkColor, &tx),
+        )?;
+        self.load_rlens(
+            "gallery_rating_color",
+            wrap_command(command::GalleryRatingColor, &tx),
+        )?;
         self.load_rlens(
             "status_bar_color",
             wrap_command(command::StatusBarColor, &tx),
@@ -426,6 +604,136 @@ fn bind_mode(
     move |ctx, (key_str, binding)| bind(&key_str, mode, binding, &keybinds, ctx)
 }
 
+/// lua callback to sort the image list
+/// The key is either the name of a sort key, or a comparator function that returns whether its
+/// first argument (an `image_details`) should be placed before its second
+fn sort(
+    request_tx: &RequestSender,
+) -> impl for<'lua> Fn(Context<'lua>, (Value<'lua>, Option<bool>)) -> Result<()> {
+    let tx = request_tx.clone();
+
+    move |ctx, (key, reverse)| {
+        let reverse = reverse.unwrap_or(false);
+
+        let less = match key {
+            Value::Function(less) => less,
+            key => {
+                let key = SortKey::from_lua(key, ctx)?;
+                return run_command(command::Sort(key, reverse), &tx, LuaContext(ctx));
+            }
+        };
+
+        // Sort the details of the images with the comparator
+        // Converting the details to lua reads those of unloaded images, so all are read first
+        let images = run_command(command::AllImages, &tx, LuaContext(ctx))?;
+        let mut images = images
+            .into_iter()
+            .map(|(key, details)| Ok((key, details.to_lua(ctx)?)))
+            .collect::<Result<Vec<_>>>()?;
+
+        // Errors cannot be returned from the sort, so the first is kept
+        let mut error = None;
+
+        // An inconsistent comparator can make the sort panic
+        catch_unwind(AssertUnwindSafe(|| {
+            images.sort_by(|(_, a), (_, b)| {
+                let ordering = compare_with(&less, a, b, &mut error);
+                if reverse {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+        }))
+        .map_err(|_| StrError("The sort comparator is inconsistent".to_string()))?;
+
+        if let Some(e) = error {
+            return Err(e);
+        }
+
+        let order = images.into_iter().map(|(key, _)| key).collect();
+        run_command(command::Reorder(order), &tx, LuaContext(ctx))
+    }
+}
+
+/// Hide the images whose details do not pass a lua predicate
+fn filter(
+    request_tx: &RequestSender,
+) -> impl for<'lua> Fn(Context<'lua>, Function<'lua>) -> Result<()> {
+    let tx = request_tx.clone();
+
+    move |ctx, keep| {
+        // The filter is cleared first so that the predicate sees every image
+        run_command(command::ClearFilter, &tx, LuaContext(ctx))?;
+
+        let mut keys = HashSet::new();
+        for (key, details) in run_command(command::AllImages, &tx, LuaContext(ctx))? {
+            if keep.call::<_, bool>(details.to_lua(ctx)?)? {
+                keys.insert(key);
+            }
+        }
+
+        run_command(command::Filter(keys), &tx, LuaContext(ctx))
+    }
+}
+
+/// Compare two values with a lua comparator that returns whether its first argument is less than
+/// its second
+/// If the comparator fails, the values are treated as equal and the first error is kept in `error`
+fn compare_with<'lua>(
+    less: &Function<'lua>,
+    a: &Value<'lua>,
+    b: &Value<'lua>,
+    error: &mut Option<rlua::Error>,
+) -> Ordering {
+    let mut less_than = |a: &Value<'lua>, b: &Value<'lua>| {
+        less.call::<_, bool>((a.clone(), b.clone()))
+            .map_err(|e| error.get_or_insert(e).clone())
+            .unwrap_or(false)
+    };
+
+    if less_than(a, b) {
+        Ordering::Less
+    } else if less_than(b, a) {
+        Ordering::Greater
+    } else {
+        Ordering::Equal
+    }
+}
+
+/// lua callback to group the images into stacks
+/// The rule is either `'stem'`, or a function from the path of an image to its stack key
+fn stack(
+    request_tx: &RequestSender,
+) -> impl for<'lua> Fn(Context<'lua>, Value<'lua>) -> Result<()> {
+    let tx = request_tx.clone();
+
+    move |ctx, rule| {
+        let rule = match rule {
+            Value::String(s) if s.to_str()? == "stem" => StackRule::Stem,
+            Value::Function(key_f) => {
+                // Find the keys of all images, including those already stacked
+                let paths = run_command(command::MemberPaths, &tx, LuaContext(ctx))?;
+                let mut keys = HashMap::new();
+                for path in paths {
+                    let key: Option<String> = key_f.call(path.to_string_lossy().into_owned())?;
+                    if let Some(key) = key {
+                        keys.insert(path, key);
+                    }
+                }
+                StackRule::Keys(keys)
+            }
+            _ => {
+                return Err(
+                    StrError("Stack rule must be `'stem'` or a function".to_string()).into(),
+                )
+            }
+        };
+
+        run_command(command::Stack(rule), &tx, LuaContext(ctx))
+    }
+}
+
 /// lua callback to bind a key to a function for all modes
 fn bind_all(
     keybinds: Arc<Mutex<KeyBinds>>,
@@ -439,21 +747,28 @@ fn bind_all(
 }
 
 impl<'lua> LuaContext<'lua> {
-    /// Try to call a nullary function by name
+    /// Try to call a function by name
     /// Returns `None` if the function was not found in the scope
-    fn call_function<T: FromLuaMulti<'lua>>(
+    fn call_function<A: ToLuaMulti<'lua>, T: FromLuaMulti<'lua>>(
         &self,
         function_ident: &str,
         scope: Table<'lua>,
+        args: A,
     ) -> Result<Option<T>> {
         let f: Option<Function> = scope.get(function_ident).ok();
-        f.map(|f| f.call(())).transpose()
+        f.map(|f| f.call(args)).transpose()
     }
 
     /// Call a hook by name
     pub fn call_hook(&self, hook: &str) -> Result<()> {
+        self.call_hook_with(hook, ())
+    }
+
+    /// Call a hook by name with arguments
+    pub fn call_hook_with<A: ToLuaMulti<'lua>>(&self, hook: &str, args: A) -> Result<()> {
         if let Ok(scope) = self.0.globals().get(HOOK_TABLE) {
-            self.call_function::<Value>(hook, scope).map(|_| ())
+            self.call_function::<A, Value>(hook, scope, args)
+                .map(|_| ())
         } else {
             Ok(())
         }
@@ -462,7 +777,7 @@ impl<'lua> LuaContext<'lua> {
     /// Call a query by name
     pub fn call_query<T: FromLuaMulti<'lua>>(&self, query: &str) -> Result<Option<T>> {
         if let Ok(scope) = self.0.globals().get(QUERY_TABLE) {
-            self.call_function(query, scope)
+            self.call_function(query, scope, ())
         } else {
             Ok(None)
         }
@@ -1,10 +1,14 @@
 #![feature(associated_type_defaults)]
 
+mod color;
 mod command;
 mod command_types;
+mod external_loader;
+mod file_ops;
 mod gallery;
 mod geometry;
 mod gfx;
+mod history;
 mod hooks;
 mod image;
 mod image_loader;
@@ -14,26 +18,40 @@ mod input;
 mod keybinds;
 mod load_request;
 mod lua;
+mod orientation;
+mod phash;
+mod playlist;
 mod program;
 mod rlens;
+mod session;
+mod sidecar;
 mod status_bar;
+mod tone_map;
 mod util;
+mod watcher;
 mod window;
 
+use color::TargetProfiles;
+use external_loader::ExternalLoaders;
+use image::ImageSource;
+use image_loader::DecodeLimits;
 use lua::ConfigFlag;
 use program::{rlens, Settings};
+use session::Session;
 use util::{touch_dir, PrintErr};
 
 use clap::Parser;
 use directories::ProjectDirs;
+use indexmap::IndexMap;
 use serde::Deserialize;
 use std::borrow::Cow;
 use std::convert::Infallible;
 use std::env::var_os;
 use std::fs;
-use std::io::stdin;
+use std::io::{stdin, Read};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 fn main() {
     main_().print_err().ok();
@@ -43,7 +61,7 @@ fn main() {
 #[derive(Parser)]
 #[clap(version)]
 struct Args {
-    /// Paths to image files
+    /// Paths to image files (`-` to read image data from stdin)
     #[clap(value_name = "PATH")]
     paths: Vec<PathBuf>,
     /// The image to start at ([1..])
@@ -58,6 +76,15 @@ struct Args {
     /// Path to the thumbnail directory
     #[clap(long, short, value_name = "DIR")]
     thumbnail_dir: Option<PathBuf>,
+    /// Print the paths of the marked images to stdout on exit
+    #[clap(long)]
+    output_marked: bool,
+    /// Restore the session saved in FILE (see `rlens.save_session`)
+    #[clap(long, value_name = "FILE", conflicts_with = "paths")]
+    session: Option<PathBuf>,
+    /// Open the images listed in the playlist FILE (see `rlens.export_list`)
+    #[clap(long, value_name = "FILE", conflicts_with_all = &["paths", "session"])]
+    playlist: Option<PathBuf>,
 }
 
 /// Configuration file contents
@@ -65,7 +92,11 @@ struct Args {
 struct Config {
     thumbnail_dir: Option<PathBuf>,
     thumbnail_size: Option<u32>,
+    loader_timeout: Option<f32>,
     font: Option<FontConfig>,
+    loaders: Option<IndexMap<String, Vec<String>>>,
+    color: Option<ColorConfig>,
+    limits: Option<LimitsConfig>,
 }
 
 #[derive(Deserialize, Default, Debug)]
@@ -74,16 +105,43 @@ struct FontConfig {
     size: Option<f32>,
 }
 
+#[derive(Deserialize, Default, Debug)]
+struct ColorConfig {
+    display_profile: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Default, Debug)]
+struct LimitsConfig {
+    max_pixels: Option<u64>,
+    /// In megabytes
+    max_alloc: Option<u64>,
+    /// In seconds
+    timeout: Option<f32>,
+}
+
 fn main_() -> Result<(), String> {
     // Parse command line args
     let args = Args::parse();
 
+    // Whether stdin has been consumed
+    let mut stdin_used = false;
+
+    // The session to restore
+    let session = args.session.as_deref().map(Session::read).transpose()?;
+
     // Build the path list
-    let paths = if !args.paths.is_empty() {
+    let paths = if let Some(session) = &session {
+        // We are restoring a session
+        session.paths().map(Path::to_path_buf).collect()
+    } else if let Some(playlist) = &args.playlist {
+        // We have been given a playlist
+        playlist::read(playlist)?
+    } else if !args.paths.is_empty() {
         // We have been given paths as command line arguments
         args.paths
     } else if !atty::is(atty::Stream::Stdin) {
         // We have data to read from stdin
+        stdin_used = true;
         stdin()
             .lines()
             .map(|s| s.map(PathBuf::from))
@@ -98,22 +156,29 @@ fn main_() -> Result<(), String> {
         return Err("Error: No paths were provided".to_string());
     }
 
+    // Build the image sources
+    let sources = paths
+        .into_iter()
+        .map(|path| image_source(path, &mut stdin_used))
+        .collect::<Result<Vec<_>, _>>()?;
+
     // Initial image
     let initial_index = args
         .initial_image
         .map(|i| {
-            if i < 1 || i > paths.len() {
+            if i < 1 || i > sources.len() {
                 Err(format!(
                     "Error: Index `{}` is out of range (max: {})",
                     i,
-                    paths.len()
+                    sources.len()
                 ))
             } else {
                 Ok(i - 1)
             }
         })
         .transpose()?
-        // Default to the first image
+        // Default to the session's image, then the first image
+        .or(session.map(|session| session.index))
         .unwrap_or(0);
 
     // Build settings from the args, config file, and defaults
@@ -161,10 +226,54 @@ fn main_() -> Result<(), String> {
         });
     touch_dir(&thumbnail_dir)?;
 
+    // History directory: Determined by a system standard
+    let history_dir = {
+        const HISTORY_DIR_NAME: &str = "history";
+        let mut p = dirs.data_dir().to_path_buf();
+        p.push(HISTORY_DIR_NAME);
+        p
+    };
+
     // Thumbnail size: Determined by the config, then a default
     const DEFAULT_THUMBNAIL_SIZE: u32 = 256;
     let thumbnail_size = config.thumbnail_size.unwrap_or(DEFAULT_THUMBNAIL_SIZE);
 
+    // External loaders: Determined by the config
+    const DEFAULT_LOADER_TIMEOUT: f32 = 10.0;
+    let loader_timeout = config.loader_timeout.unwrap_or(DEFAULT_LOADER_TIMEOUT);
+    let loader_timeout = Duration::try_from_secs_f32(loader_timeout)
+        .map_err(|_| format!("Error: Invalid loader timeout `{}`", loader_timeout))?;
+    let external_loaders =
+        ExternalLoaders::new(config.loaders.clone().unwrap_or_default(), loader_timeout);
+
+    // Color profiles: The display profile is determined by the config, then sRGB
+    let color_profiles = TargetProfiles::new(
+        config
+            .color
+            .as_ref()
+            .and_then(|c| c.display_profile.as_deref()),
+    )?;
+
+    // Decoding limits: Determined by the config, and otherwise unbounded
+    let limits = {
+        let LimitsConfig {
+            max_pixels,
+            max_alloc,
+            timeout,
+        } = config.limits.unwrap_or_default();
+        let timeout = timeout
+            .map(|t| {
+                Duration::try_from_secs_f32(t)
+                    .map_err(|_| format!("Error: Invalid decoding timeout `{}`", t))
+            })
+            .transpose()?;
+        DecodeLimits {
+            max_pixels,
+            max_alloc: max_alloc.map(|mb| mb.saturating_mul(1024 * 1024)),
+            timeout,
+        }
+    };
+
     // Font data: Determined by the config, then an embedded font
     let font_data = config
         .font
@@ -199,12 +308,68 @@ fn main_() -> Result<(), String> {
         config_flags,
         thumbnail_dir,
         thumbnail_size,
+        external_loaders,
+        color_profiles,
+        limits,
         font_data,
         font_size,
+        history_dir,
+        output_marked: args.output_marked,
+        session: args.session,
     };
 
     // Run rlens
-    rlens(paths, initial_index, settings)
+    rlens(sources, initial_index, settings)
+}
+
+/// The path which denotes image data provided by stdin
+const STDIN_PATH: &str = "-";
+
+/// Create the image source for a path
+/// Sources that can only be read once (stdin and named pipes) are read into memory
+fn image_source(path: PathBuf, stdin_used: &mut bool) -> Result<ImageSource, String> {
+    if path.as_os_str() == STDIN_PATH {
+        if *stdin_used {
+            return Err("Error: stdin can only be read once".to_string());
+        }
+        *stdin_used = true;
+
+        let mut data = Vec::new();
+        stdin()
+            .read_to_end(&mut data)
+            .map_err(|e| format!("Error reading image data from stdin: {}", e))?;
+
+        return Ok(ImageSource::Memory {
+            path,
+            data: data.into(),
+        });
+    }
+
+    if is_fifo(&path) {
+        let data = fs::read(&path)
+            .map_err(|e| format!("Error reading from pipe at `{}`: {}", path.display(), e))?;
+
+        return Ok(ImageSource::Memory {
+            path,
+            data: data.into(),
+        });
+    }
+
+    Ok(ImageSource::File(path))
+}
+
+/// Check if the file at `path` is a named pipe
+fn is_fifo(path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        fs::metadata(path)
+            .map(|m| m.file_type().is_fifo())
+            .unwrap_or(false)
+    }
+
+    #[cfg(not(unix))]
+    false
 }
 
 /// Attempt to get the details of the config file
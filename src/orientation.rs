@@ -0,0 +1,347 @@
+//! Module for saving the orientation of images to their files
+//!
+//! JPEGs are edited losslessly by setting their EXIF Orientation tag.
+//! PNG, BMP and TIFF images are re-encoded in their format with the orientation applied to their
+//! pixel data, which drops their other metadata. Images that would lose more than that, such as
+//! animated images and images with an embedded color profile, are refused.
+
+use crate::image_transform::Orientation;
+use crate::util::write_atomically;
+
+use image::io::Reader as ImageReader;
+use image::ImageFormat;
+use std::fs;
+use std::io::Cursor;
+use std::path::Path;
+
+/// The JPEG start of image marker
+const SOI: [u8; 2] = [0xff, 0xd8];
+/// The JPEG marker of the APP0 segment, which holds the JFIF header
+const APP0: u8 = 0xe0;
+/// The JPEG marker of the APP1 segment, which holds the EXIF data
+const APP1: u8 = 0xe1;
+/// The JPEG start of scan marker, after which the image data follows
+const SOS: u8 = 0xda;
+/// The prefix of the EXIF data in the APP1 segment
+const EXIF_PREFIX: &[u8] = b"Exif\0\0";
+/// The EXIF Orientation tag
+const ORIENTATION_TAG: u16 = 0x0112;
+/// The TIFF type of 16-bit unsigned integers
+const SHORT_TYPE: u16 = 3;
+/// The TIFF tag of the offsets of sub-images
+const SUB_IFDS_TAG: u16 = 330;
+/// The TIFF tag of the embedded ICC profile
+const ICC_PROFILE_TAG: u16 = 34675;
+/// The signature of the PNG format
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+/// The size of a BMP file header
+const BMP_FILE_HEADER_LEN: usize = 14;
+/// The size of a BMP V5 info header, the first with a color space
+const BMP_V5_HEADER_LEN: u32 = 124;
+/// The color space of BMPs with an embedded ICC profile ('MBED' in little-endian order)
+const BMP_PROFILE_EMBEDDED: &[u8] = b"DEBM";
+
+/// Save `orientation` as the orientation of the image at `path`
+/// `orientation` is relative to the image's pixel data, replacing any EXIF orientation
+pub fn save_orientation(path: &Path, orientation: Orientation) -> Result<(), String> {
+    let orientation_err = |e: &dyn std::fmt::Display| {
+        format!(
+            "Error: Failed to save the orientation of `{}`: {}",
+            path.display(),
+            e
+        )
+    };
+
+    let data = fs::read(path).map_err(|e| orientation_err(&e))?;
+
+    let data = if data.starts_with(&SOI) {
+        set_jpeg_orientation(data, orientation).map_err(|e| orientation_err(&e))?
+    } else {
+        reencode(&data, path, orientation).map_err(|e| orientation_err(&e))?
+    };
+
+    let permissions = fs::metadata(path).map(|m| m.permissions()).ok();
+    write_atomically(path, |temp_path| {
+        fs::write(temp_path, data).map_err(|e| e.to_string())?;
+        if let Some(permissions) = permissions {
+            fs::set_permissions(temp_path, permissions).map_err(|e| e.to_string())?;
+        }
+        Ok(())
+    })
+}
+
+/// Re-encode the image in `data` in its format with `orientation` applied to its pixel data
+fn reencode(data: &[u8], path: &Path, orientation: Orientation) -> Result<Vec<u8>, String> {
+    let reader = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()
+        .map_err(|e| e.to_string())?;
+    let format = reader
+        .format()
+        .ok_or_else(|| "Unknown image format".to_string())?;
+
+    // The contents must match the extension, so that e.g. a TIFF-based RAW is not replaced by
+    // its preview
+    if ImageFormat::from_path(path).ok() != Some(format) {
+        return Err("The contents of the file do not match its extension".to_string());
+    }
+    check_reencodable(data, format)?;
+
+    let image = reader.decode().map_err(|e| e.to_string())?;
+
+    let image = orientation.apply(image);
+
+    let mut encoded = Cursor::new(Vec::new());
+    image
+        .write_to(&mut encoded, format)
+        .map_err(|e| e.to_string())?;
+    Ok(encoded.into_inner())
+}
+
+/// Check that the image in `data` loses nothing but its other metadata when re-encoded
+fn check_reencodable(data: &[u8], format: ImageFormat) -> Result<(), String> {
+    let animated = || Err("Animated images cannot be re-encoded".to_string());
+    let profile = || Err("Images with an embedded color profile cannot be re-encoded".to_string());
+    let malformed = || Err(format!("Malformed {:?} image", format));
+
+    match format {
+        ImageFormat::Png => {
+            // The animation and the profile must be declared before the image data
+            let mut pos = PNG_SIGNATURE.len();
+            while let Some(header) = data.get(pos..pos + 8) {
+                let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+                match &header[4..] {
+                    b"acTL" => return animated(),
+                    b"iCCP" => return profile(),
+                    b"IDAT" | b"IEND" => return Ok(()),
+                    _ => pos += 12 + len as usize,
+                }
+            }
+            malformed()
+        }
+        ImageFormat::Bmp => {
+            let header_len = match data.get(BMP_FILE_HEADER_LEN..BMP_FILE_HEADER_LEN + 4) {
+                Some(bytes) => u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                None => return malformed(),
+            };
+            let color_space = BMP_FILE_HEADER_LEN + 56;
+            if header_len >= BMP_V5_HEADER_LEN
+                && data.get(color_space..color_space + 4) == Some(BMP_PROFILE_EMBEDDED)
+            {
+                return profile();
+            }
+            Ok(())
+        }
+        ImageFormat::Tiff => {
+            let order = match ByteOrder::of(data) {
+                Some(order) => order,
+                None => return malformed(),
+            };
+            let (ifd, entries) = match first_ifd(data, order) {
+                Some(ifd) => ifd,
+                None => return malformed(),
+            };
+            for i in 0..entries {
+                match order.read_u16(data, ifd + 2 + i * 12) {
+                    Some(SUB_IFDS_TAG) => return animated(),
+                    Some(ICC_PROFILE_TAG) => return profile(),
+                    Some(_) => {}
+                    None => return malformed(),
+                }
+            }
+            // Further IFDs hold more images, such as pages or a preview
+            match order.read_u32(data, ifd + 2 + entries * 12) {
+                Some(0) => Ok(()),
+                Some(_) => Err("TIFFs with more than one image cannot be re-encoded".to_string()),
+                None => malformed(),
+            }
+        }
+        _ => Err(format!(
+            "Only JPEG, PNG, BMP and TIFF images can be saved, not {:?}",
+            format
+        )),
+    }
+}
+
+/// Set the EXIF Orientation tag of the JPEG in `data`
+/// An EXIF segment is added if the JPEG has none
+fn set_jpeg_orientation(mut data: Vec<u8>, orientation: Orientation) -> Result<Vec<u8>, String> {
+    let value = orientation.exif() as u16;
+
+    // Walk the segments before the image data
+    // The EXIF segment is placed after the JFIF segment (APP0) if there is one
+    let mut insert_at = SOI.len();
+    let mut pos = SOI.len();
+    while pos + 4 <= data.len() && data[pos] == 0xff {
+        let marker = data[pos + 1];
+        if marker == SOS {
+            break;
+        }
+        if marker == 0xff {
+            // Fill byte
+            pos += 1;
+            continue;
+        }
+
+        let len = u16::from_be_bytes([data[pos + 2], data[pos + 3]]) as usize;
+        let segment = pos + 4..pos + 2 + len;
+        if len < 2 || segment.end > data.len() {
+            return Err("Malformed JPEG segment".to_string());
+        }
+
+        if marker == APP1 && data[segment.clone()].starts_with(EXIF_PREFIX) {
+            let tiff_range = segment.start + EXIF_PREFIX.len()..segment.end;
+            let mut tiff = data[tiff_range.clone()].to_vec();
+            set_tiff_orientation(&mut tiff, value)?;
+
+            let len = u16::try_from(2 + EXIF_PREFIX.len() + tiff.len())
+                .map_err(|_| "The EXIF data is too large to add the Orientation tag".to_string())?;
+            data[pos + 2..pos + 4].copy_from_slice(&len.to_be_bytes());
+            data.splice(tiff_range, tiff);
+            return Ok(data);
+        }
+        if marker == APP0 {
+            insert_at = segment.end;
+        }
+
+        pos = segment.end;
+    }
+
+    // Add an EXIF segment with only the orientation
+    let mut tiff = Vec::new();
+    tiff.extend_from_slice(b"MM\0\x2a");
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    tiff.extend_from_slice(&1u16.to_be_bytes());
+    tiff.extend_from_slice(&ORIENTATION_TAG.to_be_bytes());
+    tiff.extend_from_slice(&SHORT_TYPE.to_be_bytes());
+    tiff.extend_from_slice(&1u32.to_be_bytes());
+    tiff.extend_from_slice(&value.to_be_bytes());
+    tiff.extend_from_slice(&[0, 0]);
+    tiff.extend_from_slice(&0u32.to_be_bytes());
+
+    let len = (2 + EXIF_PREFIX.len() + tiff.len()) as u16;
+    let mut segment = vec![0xff, APP1];
+    segment.extend_from_slice(&len.to_be_bytes());
+    segment.extend_from_slice(EXIF_PREFIX);
+    segment.extend_from_slice(&tiff);
+
+    data.splice(insert_at..insert_at, segment);
+    Ok(data)
+}
+
+/// Set the Orientation tag in the first IFD of the TIFF structure in `tiff`
+/// If the tag is not present, the IFD is rewritten at the end of the structure with the tag added,
+/// so that nothing the other offsets point to moves
+fn set_tiff_orientation(tiff: &mut Vec<u8>, value: u16) -> Result<(), String> {
+    let malformed = || "Malformed EXIF data".to_string();
+
+    let order = ByteOrder::of(tiff).ok_or_else(malformed)?;
+    let (ifd, entries) = first_ifd(tiff, order).ok_or_else(malformed)?;
+
+    // Entries are sorted by their tag
+    let mut insert_at = entries;
+    for i in 0..entries {
+        let entry = ifd + 2 + i * 12;
+        let tag = order.read_u16(tiff, entry).ok_or_else(malformed)?;
+        if tag == ORIENTATION_TAG {
+            if order.read_u16(tiff, entry + 2) != Some(SHORT_TYPE) {
+                return Err("Unsupported type of the Orientation tag".to_string());
+            }
+            let field = tiff.get_mut(entry + 8..entry + 10).ok_or_else(malformed)?;
+            field.copy_from_slice(&order.u16_bytes(value));
+            return Ok(());
+        }
+        if tag > ORIENTATION_TAG {
+            insert_at = i;
+            break;
+        }
+    }
+
+    let entries_start = ifd + 2;
+    let entries_end = entries_start + entries * 12;
+    let old_entries = tiff
+        .get(entries_start..entries_end)
+        .ok_or_else(malformed)?
+        .to_vec();
+    let next_ifd = tiff
+        .get(entries_end..entries_end + 4)
+        .ok_or_else(malformed)?
+        .to_vec();
+
+    let mut orientation_entry = Vec::with_capacity(12);
+    orientation_entry.extend_from_slice(&order.u16_bytes(ORIENTATION_TAG));
+    orientation_entry.extend_from_slice(&order.u16_bytes(SHORT_TYPE));
+    orientation_entry.extend_from_slice(&order.u32_bytes(1));
+    orientation_entry.extend_from_slice(&order.u16_bytes(value));
+    orientation_entry.extend_from_slice(&[0, 0]);
+
+    // IFDs start on a word boundary
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let new_ifd = u32::try_from(tiff.len()).map_err(|_| malformed())?;
+    let new_entries = u16::try_from(entries + 1).map_err(|_| malformed())?;
+
+    tiff.extend_from_slice(&order.u16_bytes(new_entries));
+    tiff.extend_from_slice(&old_entries[..insert_at * 12]);
+    tiff.extend_from_slice(&orientation_entry);
+    tiff.extend_from_slice(&old_entries[insert_at * 12..]);
+    tiff.extend_from_slice(&next_ifd);
+    tiff[4..8].copy_from_slice(&order.u32_bytes(new_ifd));
+
+    Ok(())
+}
+
+/// The offset and number of entries of the first IFD of the TIFF structure in `tiff`
+fn first_ifd(tiff: &[u8], order: ByteOrder) -> Option<(usize, usize)> {
+    let ifd = order.read_u32(tiff, 4)? as usize;
+    let entries = order.read_u16(tiff, ifd)? as usize;
+    Some((ifd, entries))
+}
+
+/// The byte order of a TIFF structure
+#[derive(Clone, Copy)]
+enum ByteOrder {
+    Big,
+    Little,
+}
+
+impl ByteOrder {
+    /// Read the byte order from the header of the TIFF structure in `tiff`
+    fn of(tiff: &[u8]) -> Option<Self> {
+        match tiff.get(..2)? {
+            b"MM" => Some(Self::Big),
+            b"II" => Some(Self::Little),
+            _ => None,
+        }
+    }
+
+    fn read_u16(self, data: &[u8], at: usize) -> Option<u16> {
+        let bytes = data.get(at..at + 2)?.try_into().ok()?;
+        Some(match self {
+            Self::Big => u16::from_be_bytes(bytes),
+            Self::Little => u16::from_le_bytes(bytes),
+        })
+    }
+
+    fn read_u32(self, data: &[u8], at: usize) -> Option<u32> {
+        let bytes = data.get(at..at + 4)?.try_into().ok()?;
+        Some(match self {
+            Self::Big => u32::from_be_bytes(bytes),
+            Self::Little => u32::from_le_bytes(bytes),
+        })
+    }
+
+    fn u16_bytes(self, value: u16) -> [u8; 2] {
+        match self {
+            Self::Big => value.to_be_bytes(),
+            Self::Little => value.to_le_bytes(),
+        }
+    }
+
+    fn u32_bytes(self, value: u32) -> [u8; 4] {
+        match self {
+            Self::Big => value.to_be_bytes(),
+            Self::Little => value.to_le_bytes(),
+        }
+    }
+}
@@ -0,0 +1,90 @@
+//! Module for perceptual hashes of images, used to find duplicates and near-duplicates
+//!
+//! Hashes are difference hashes (dHash): the image is shrunk to a 9x8 grayscale grid, and each
+//! bit records whether a cell is brighter than its right neighbour.
+//! Visually similar images have hashes that differ in few bits.
+
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::collections::HashMap;
+
+/// The perceptual hash of an image
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
+pub struct ImageHash(u64);
+
+impl ImageHash {
+    /// Compute the hash of an image
+    pub fn compute(image: &DynamicImage) -> Self {
+        const WIDTH: u32 = 9;
+        const HEIGHT: u32 = 8;
+
+        let grid = image
+            .resize_exact(WIDTH, HEIGHT, FilterType::Triangle)
+            .into_luma8();
+
+        let mut hash = 0;
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH - 1 {
+                let brighter = grid.get_pixel(x, y)[0] > grid.get_pixel(x + 1, y)[0];
+                hash = (hash << 1) | u64::from(brighter);
+            }
+        }
+
+        Self(hash)
+    }
+
+    /// The number of bits that differ between two hashes
+    /// `0` for identical hashes, up to `64`
+    pub fn distance(&self, other: &Self) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+
+    /// Format the hash as a hex string
+    pub fn to_hex(self) -> String {
+        format!("{:016x}", self.0)
+    }
+
+    /// Parse a hash from a hex string
+    pub fn from_hex(s: &str) -> Option<Self> {
+        u64::from_str_radix(s.trim(), 16).ok().map(Self)
+    }
+}
+
+/// Group the items whose hashes are within `threshold` bits of each other
+/// Items are grouped transitively, and only groups with more than one item are returned
+/// Each group is in the order of `hashes`, as are the groups by their first item
+pub fn group_similar<T: Copy>(hashes: &[(T, ImageHash)], threshold: u32) -> Vec<Vec<T>> {
+    // Union-find over the positions in `hashes`
+    let mut parents = (0..hashes.len()).collect::<Vec<_>>();
+    fn root(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    for i in 0..hashes.len() {
+        for j in i + 1..hashes.len() {
+            if hashes[i].1.distance(&hashes[j].1) <= threshold {
+                let (a, b) = (root(&mut parents, i), root(&mut parents, j));
+                parents[a.max(b)] = a.min(b);
+            }
+        }
+    }
+
+    // Collect the groups in order of their first item
+    let mut groups: Vec<Vec<T>> = Vec::new();
+    let mut group_of_root = HashMap::new();
+    for (i, &(item, _)) in hashes.iter().enumerate() {
+        let root = root(&mut parents, i);
+        let group = *group_of_root.entry(root).or_insert_with(|| {
+            groups.push(Vec::new());
+            groups.len() - 1
+        });
+        groups[group].push(item);
+    }
+
+    groups.retain(|group| group.len() > 1);
+    groups
+}
@@ -0,0 +1,90 @@
+//! Module for playlists, which store image lists as files
+//!
+//! A playlist has one path per line, in the style of M3U. Blank lines and lines starting with
+//! `#` are ignored, and relative paths are relative to the directory of the playlist.
+
+use crate::util::{absolute_path, write_atomically};
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// The first line of written playlists
+const PLAYLIST_HEADER: &str = "#EXTM3U";
+
+/// Read the paths of the playlist at `path`
+pub fn read(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| format!("Error reading playlist at `{}`: {}", path.display(), e))?;
+
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    Ok(parse(&text, dir))
+}
+
+/// Parse the paths of a playlist in the directory `dir`
+pub fn parse(text: &str, dir: &Path) -> Vec<PathBuf> {
+    text.lines()
+        .map(|line| line.trim_end_matches('\r'))
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .map(|line| dir.join(line))
+        .collect()
+}
+
+/// Write `paths` as a playlist at `path`
+/// Paths are written relative to the directory of the playlist where possible
+pub fn write<'a>(path: &Path, paths: impl IntoIterator<Item = &'a Path>) -> Result<(), String> {
+    let playlist_err = |e: &dyn std::fmt::Display| {
+        format!("Error writing playlist at `{}`: {}", path.display(), e)
+    };
+
+    let dir = absolute_path(path.parent().unwrap_or_else(|| Path::new("")));
+
+    let mut text = format!("{}\n", PLAYLIST_HEADER);
+    for image_path in paths {
+        let relative = relative_path(&absolute_path(image_path), &dir);
+        let line = relative.to_str().ok_or_else(|| {
+            playlist_err(&format!("`{}` is not valid UTF-8", image_path.display()))
+        })?;
+        if line.contains('\n') {
+            return Err(playlist_err(&format!(
+                "`{}` contains a newline",
+                image_path.display()
+            )));
+        }
+
+        text.push_str(line);
+        text.push('\n');
+    }
+
+    write_atomically(path, |temp_path| {
+        fs::write(temp_path, text).map_err(|e| e.to_string())
+    })
+}
+
+/// The path of `path` relative to `base`
+/// `path` is returned unchanged if they do not share a root
+/// Pre: `path` and `base` are absolute
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    let mut path_components = path.components().peekable();
+    let mut base_components = base.components().peekable();
+
+    // Skip the common ancestors
+    let mut common = 0;
+    while let (Some(a), Some(b)) = (path_components.peek(), base_components.peek()) {
+        if a != b {
+            break;
+        }
+        path_components.next();
+        base_components.next();
+        common += 1;
+    }
+
+    // Paths on different roots (e.g. drives) cannot be relative
+    if common == 0 {
+        return path.to_path_buf();
+    }
+
+    base_components
+        .map(|_| Component::ParentDir)
+        .chain(path_components)
+        .collect()
+}
@@ -1,15 +1,21 @@
 //! Module for overall program structure and the event loop
 
+use crate::color::TargetProfiles;
 use crate::command::CommandRequestT;
+use crate::command_types::{FileChangeDetails, LoadFailedDetails};
+use crate::external_loader::ExternalLoaders;
 use crate::geometry::Size;
 use crate::gfx::Gfx;
+use crate::history::History;
 use crate::hooks::ExternalHook;
-use crate::image_loader::run_image_loader;
+use crate::image::{ImageKey, ImageSource, LoadError};
+use crate::image_loader::{run_image_loader, DecodeLimits, LoaderSettings};
 use crate::input::Key;
-use crate::load_request::{LoadRequest, LoadRequestResponse};
+use crate::load_request::{HashResponse, LoadRequest, LoadRequestResponse, SortResponse};
 use crate::lua::{ConfigFlag, Lua};
 use crate::rlens::{Mode, RLens};
 use crate::util::{PrintErr, PrintLuaErr};
+use crate::watcher::{FileChange, FileChangeKind, Watcher};
 use crate::window::Window;
 
 use std::borrow::Cow;
@@ -24,13 +30,28 @@ use winit::{
 };
 
 /// Run rlens and exit safely
-pub fn rlens(images: Vec<PathBuf>, initial_index: usize, settings: Settings) -> Result<(), String> {
+pub fn rlens(
+    images: Vec<ImageSource>,
+    initial_index: usize,
+    settings: Settings,
+) -> Result<(), String> {
+    let output_marked = settings.output_marked;
+
     let (mut program, event_loop) = Program::init(images, initial_index, settings)?;
 
     program.run(event_loop);
 
+    let marked = program.rlens.marked_paths();
+    program.record_history();
+
     program.shutdown();
 
+    if output_marked {
+        for path in marked {
+            println!("{}", path.display());
+        }
+    }
+
     Ok(())
 }
 
@@ -45,6 +66,15 @@ pub struct Program {
     /// Exit flag
     pub exit: bool,
 
+    /// Watcher for changes to the image list's files
+    pub watcher: Watcher,
+
+    /// History of viewed image lists
+    pub history: History,
+
+    /// Directory of the thumbnail cache
+    pub thumbnail_dir: PathBuf,
+
     /// Sender for lua requests
     lua_request_tx: Sender<LuaRequest>,
     /// Handle to the lua thread
@@ -71,15 +101,27 @@ pub struct Settings {
     /// Size to generate thumbnails at
     /// (fit within 'size x size')
     pub thumbnail_size: u32,
+    /// Commands for decoding images that rlens does not support
+    pub external_loaders: ExternalLoaders,
+    /// Profiles to convert images into
+    pub color_profiles: TargetProfiles,
+    /// Limits on decoding images
+    pub limits: DecodeLimits,
     /// Raw font data from a ttf/otf
     pub font_data: Cow<'static, [u8]>,
     /// Font size in pixels
     pub font_size: f32,
+    /// Path of the directory of the history of viewed image lists
+    pub history_dir: PathBuf,
+    /// Whether to print the paths of the marked images on exit
+    pub output_marked: bool,
+    /// The session to restore on startup
+    pub session: Option<PathBuf>,
 }
 
 impl Program {
     fn init(
-        images: Vec<PathBuf>,
+        images: Vec<ImageSource>,
         initial_index: usize,
         settings: Settings,
     ) -> Result<(Self, EventLoop), String> {
@@ -89,7 +131,7 @@ impl Program {
 
         let gfx = Gfx::init(window, &settings.font_data, settings.font_size)?;
 
-        let rlens = RLens::init(images, initial_index);
+        let rlens = RLens::init(images, initial_index, settings.thumbnail_size);
 
         let lua = Lua::init(request_tx.clone(), settings.config_flags)?;
 
@@ -97,12 +139,24 @@ impl Program {
         lua_request_tx
             .send(LuaRequest::RunRC(settings.rc_path))
             .unwrap();
+        // The session is restored after the rc so that it takes precedence
+        if let Some(session) = settings.session {
+            lua_request_tx
+                .send(LuaRequest::LoadSession(session))
+                .unwrap();
+        }
 
-        let (load_request_tx, image_loader_thread) = run_image_loader(
-            request_tx.clone(),
-            settings.thumbnail_dir,
-            settings.thumbnail_size,
-        );
+        let loader_settings = LoaderSettings {
+            thumbnail_dir: settings.thumbnail_dir.clone(),
+            thumbnail_size: settings.thumbnail_size,
+            external_loaders: settings.external_loaders,
+            color_profiles: settings.color_profiles,
+            limits: settings.limits,
+        };
+        let (load_request_tx, image_loader_thread) =
+            run_image_loader(request_tx.clone(), loader_settings);
+
+        let watcher = Watcher::new(request_tx);
 
         let program = Self {
             rlens,
@@ -117,6 +171,12 @@ impl Program {
             image_loader_thread,
 
             exit: false,
+
+            watcher,
+
+            history: History::new(settings.history_dir),
+
+            thumbnail_dir: settings.thumbnail_dir,
         };
 
         Ok((program, event_loop))
@@ -208,6 +268,17 @@ impl Program {
 
 /// Helpers
 impl Program {
+    /// Record the current open image in the history of the image list
+    fn record_history(&self) {
+        let current = &self.rlens.images()[self.rlens.current_open_image()];
+        if let Some(path) = current.source().file_path() {
+            self.history
+                .record(self.rlens.file_paths(), path)
+                .print_err()
+                .ok();
+        }
+    }
+
     /// Draw the state of the program
     pub fn draw(&mut self) {
         let view = self.window_size();
@@ -252,11 +323,18 @@ pub enum Request {
     /// This is made by the image loader thread immediately before waiting for a request
     ImageLoaderReady,
     /// Load an image from the raw data
-    LoadImage(LoadRequestResponse),
-    /// Mark an image's source as unloadable
-    MarkUnloadable(usize),
+    LoadImage(Box<LoadRequestResponse>),
+    /// Mark an image's source as unloadable due to an error
+    MarkUnloadable(usize, ImageKey, LoadError),
+    /// Set the perceptual hash of an image
+    SetHash(HashResponse),
+    /// Sort the image list by the order read by the image loader
+    SortOrder(SortResponse),
     /// Unload any out-of-range images
     UnloadImages,
+
+    /// A watched file has changed
+    FileEvent(notify::Event),
 }
 
 type Event<'a> = event::Event<'a, Request>;
@@ -282,12 +360,23 @@ impl Program {
                     self.image_loader_waiting = true;
                 }
             }
-            Request::LoadImage(LoadRequestResponse {
-                type_,
-                index,
-                image,
-                metadata,
-            }) => {
+            Request::LoadImage(response) => {
+                let LoadRequestResponse {
+                    type_,
+                    index,
+                    key,
+                    image,
+                    metadata,
+                } = *response;
+
+                // Find the image, which may have moved or been removed since the request
+                let index = match self.rlens.resolve_image(index, key) {
+                    Some(index) => index,
+                    None => {
+                        return;
+                    }
+                };
+
                 // Load the image into the canvas
                 let loaded = match image.load_into_canvas(&mut self.gfx).print_err() {
                     Ok(loaded) => loaded,
@@ -299,7 +388,7 @@ impl Program {
                 // Update the image list
                 let (redraw, current_load) =
                     self.rlens
-                        .set_loaded(type_, index, loaded, metadata, self.window_size());
+                        .set_loaded(type_, index, loaded, metadata, &mut self.gfx);
 
                 if redraw {
                     self.draw();
@@ -310,12 +399,96 @@ impl Program {
                         .unwrap();
                 }
             }
-            Request::MarkUnloadable(index) => {
-                self.rlens.mark_unloadable(index);
+            Request::MarkUnloadable(index, key, error) => {
+                if let Some(index) = self.rlens.resolve_image(index, key) {
+                    let path = self.rlens.get_image(index).path().to_path_buf();
+                    self.rlens.mark_unloadable(index, error.clone());
+
+                    // Show the failed tile
+                    if self.rlens.mode() == Mode::Gallery {
+                        self.draw();
+                    }
+
+                    let details = LoadFailedDetails::new(index, path, error);
+                    self.lua_request_tx
+                        .send(LuaRequest::Hook(ExternalHook::ImageLoadFailed(details)))
+                        .unwrap();
+                }
+            }
+            Request::SetHash(HashResponse { index, key, hash }) => {
+                if let Some(index) = self.rlens.resolve_image(index, key) {
+                    self.rlens.set_hash(index, hash);
+                }
+            }
+            Request::SortOrder(response) => {
+                if self.rlens.finish_sort(response, &mut self.gfx) {
+                    self.wake_image_loader();
+                    self.draw();
+                }
             }
             Request::UnloadImages => {
                 self.rlens.unload_images(&mut self.gfx);
             }
+
+            Request::FileEvent(event) => {
+                for change in self.watcher.changes(event) {
+                    self.on_file_change(change);
+                }
+            }
+        }
+    }
+
+    /// Respond to a change in a watched file
+    fn on_file_change(&mut self, change: FileChange) {
+        let FileChange { kind, path } = change;
+
+        let mut current_image_change = false;
+
+        match kind {
+            FileChangeKind::Modify => {
+                // Reload the images from the file
+                for index in self.rlens.image_indices(&path) {
+                    self.rlens.refresh_image(index, &mut self.gfx);
+                }
+                self.rlens.refresh_hidden(&path, &mut self.gfx);
+                self.wake_image_loader();
+            }
+            FileChangeKind::Create => {
+                // Add the new image
+                self.rlens.push_image(ImageSource::File(path.clone()));
+                self.watcher.add(&path);
+                self.wake_image_loader();
+                self.draw();
+            }
+            FileChangeKind::Remove => {
+                // Drop the images from the image list if we are tracking directories
+                // The last image is always kept
+                if self.watcher.track_directories() {
+                    for index in self.rlens.image_indices(&path).into_iter().rev() {
+                        if self.rlens.total_images() > 1 {
+                            current_image_change |= self.rlens.remove_image(index, &mut self.gfx);
+                        }
+                    }
+                    self.rlens.remove_hidden(&path, &mut self.gfx);
+                    if self.rlens.image_indices(&path).is_empty() {
+                        self.watcher.remove(&path);
+                    }
+                    self.wake_image_loader();
+                    self.draw();
+                }
+            }
+        }
+
+        // Call lua hooks
+        let index = self.rlens.image_indices(&path).first().copied();
+        let details = FileChangeDetails::new(kind, path, index);
+        self.lua_request_tx
+            .send(LuaRequest::Hook(ExternalHook::FileChange(details)))
+            .unwrap();
+        if current_image_change {
+            self.lua_request_tx
+                .send(LuaRequest::Hook(ExternalHook::CurrentImageChange))
+                .unwrap();
         }
     }
 }
@@ -345,6 +518,8 @@ enum LuaRequest {
     Hook(ExternalHook),
     /// Run the RC
     RunRC(PathBuf),
+    /// Restore a session
+    LoadSession(PathBuf),
 }
 
 impl LuaRequest {
@@ -360,6 +535,9 @@ impl LuaRequest {
             Self::RunRC(rc_path) => {
                 lua.run_rc(&rc_path).print_err().ok();
             }
+            Self::LoadSession(path) => {
+                lua.load_session(path).print_lua_err().ok();
+            }
         }
     }
 }
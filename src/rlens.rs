@@ -3,25 +3,46 @@
 use crate::gallery::Gallery;
 use crate::geometry::*;
 use crate::gfx::{CanvasExt, Font, Gfx, CLEAR};
-use crate::image::{Image, LoadedImage, Metadata};
+use crate::image::{Image, ImageKey, ImageSource, LoadError, LoadedImage, Metadata};
+use crate::image_loader::{read_date_taken, read_dimensions};
 use crate::image_transform::{Align, ImageTransform, Scaling};
 use crate::image_view::ImageView;
-use crate::load_request::{FullRequest, ImageType, LoadRequest, ThumbnailRequest};
+use crate::load_request::{
+    FullRequest, HashRequest, ImageType, LoadRequest, SortImage, SortRequest, SortResponse,
+    ThumbnailRequest,
+};
+use crate::orientation::save_orientation;
+use crate::phash::{group_similar, ImageHash};
+use crate::session::{Session, SessionCollection, SessionImage, SessionTransform};
+use crate::sidecar::{read_tags, write_tags, ImageTags};
 use crate::status_bar::{StatusBar, StatusBarPosition};
-use crate::util::Offset;
+use crate::tone_map::ToneMapping;
+use crate::util::{absolute_path, Offset, PrintErr};
 
 use enum_map::Enum;
 use femtovg::Color;
-use std::path::PathBuf;
+use lexical_sort::natural_lexical_cmp;
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::iter;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 /// State of rlens
 pub struct RLens {
     /// The current view
     mode: Mode,
 
-    /// The image list
+    /// The image list, or the images that pass the filter if one is set
     /// Non-empty
-    images: Box<[Image]>,
+    images: Vec<Image>,
+    /// The images hidden by the filter
+    /// Each run of hidden images is keyed by the visible image it follows (`None` at the start)
+    hidden: HashMap<Option<ImageKey>, Vec<Image>>,
 
     /// The image view
     image_view: ImageView,
@@ -34,8 +55,28 @@ pub struct RLens {
 
     /// The gallery
     gallery: Gallery,
+
+    /// The collections, which are independent image lists
+    /// The active collection is held in the fields above, and its place here is `None`
+    collections: Vec<Option<Collection>>,
+
     /// Whether to save generated thumbnails
     save_thumbnails: bool,
+    /// The smallest size that thumbnails are requested at
+    /// Larger sizes are requested when the gallery tiles are larger than this
+    thumbnail_size: u32,
+    /// Whether the perceptual hashes of the images are computed in the background
+    hash_images: bool,
+
+    /// A sort whose values are waiting to be read by the image loader
+    pending_sort: Option<SortRequest>,
+    /// The generation of the latest sort or reorder, which supersedes earlier sorts
+    sort_generation: u64,
+
+    /// Whether images are converted from their embedded color profiles
+    color_management: bool,
+    /// Tone mapping for high precision images
+    tone_mapping: ToneMapping,
 
     /// The status bar
     status_bar: StatusBar,
@@ -52,8 +93,47 @@ pub struct RLens {
     frozen: bool,
 }
 
+/// An inactive image list, with the state of its image view and gallery
+struct Collection {
+    images: Vec<Image>,
+    hidden: HashMap<Option<ImageKey>, Vec<Image>>,
+    image_view: ImageView,
+    gallery: Gallery,
+}
+
+impl Collection {
+    /// Pre: `sources` is not empty
+    fn new(sources: Vec<ImageSource>) -> Self {
+        assert!(!sources.is_empty());
+
+        Self {
+            images: sources.into_iter().map(Image::new_unloaded).collect(),
+            hidden: HashMap::new(),
+            image_view: ImageView::init(0),
+            gallery: Gallery::init(),
+        }
+    }
+
+    /// Iterator over the images in the order of the image list without the filter
+    fn all_images(&self) -> impl Iterator<Item = &Image> {
+        all_images(&self.images, &self.hidden)
+    }
+
+    /// Unload the images of the collection
+    fn unload(self, gfx: &mut Gfx) {
+        for image in self
+            .images
+            .into_iter()
+            .chain(self.hidden.into_values().flatten())
+        {
+            image.into_source(gfx);
+        }
+    }
+}
+
 /// A mode in rlens
-#[derive(Enum, Clone, Copy, PartialEq, Default, Debug)]
+#[derive(Enum, Clone, Copy, PartialEq, Default, Serialize, Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
 pub enum Mode {
     /// The image mode
     #[default]
@@ -65,16 +145,145 @@ pub enum Mode {
 /// rlens modes
 pub const MODES: &[Mode] = &[Mode::Image, Mode::Gallery];
 
+/// A key to sort the image list by
+#[derive(Clone, Copy, Debug)]
+pub enum SortKey {
+    /// The filename, in natural order (e.g. `2.png` before `10.png`)
+    Name,
+    /// The time of the last modification of the file
+    Modified,
+    /// The size of the file
+    Size,
+    /// The number of pixels in the image
+    Dimensions,
+    /// The date and time the image was taken, from its EXIF data
+    DateTaken,
+    /// The star rating, from the image's sidecar
+    Rating,
+}
+
+/// The value of an image for a sort key
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum SortValue {
+    Name(NaturalName),
+    Time(SystemTime),
+    Number(u64),
+    Text(String),
+}
+
+/// A name that is ordered naturally
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct NaturalName(String);
+
+impl Ord for NaturalName {
+    fn cmp(&self, other: &Self) -> Ordering {
+        natural_lexical_cmp(&self.0, &other.0)
+    }
+}
+
+impl PartialOrd for NaturalName {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl SortKey {
+    /// Get the value of an image for the key from its name or loaded metadata
+    /// `None` if the value has to be read from the source (see `read_value`)
+    fn known_value(self, image: &Image) -> Option<SortValue> {
+        let metadata = image.metadata.loaded();
+
+        match self {
+            Self::Name => Some(name_value(image.path())),
+            Self::Modified | Self::Size => None,
+            Self::Dimensions => metadata.map(|m| pixels_value(m.dimensions)),
+            Self::DateTaken => metadata?
+                .exif
+                .as_ref()?
+                .date_taken
+                .clone()
+                .map(SortValue::Text),
+            Self::Rating => metadata.map(|m| SortValue::Number(m.tags.stars().into())),
+        }
+    }
+
+    /// Read the value of the image from `source` for the key, if it has one
+    /// This reads from the disk, so it should be called from the image loader
+    pub fn read_value(self, source: &ImageSource) -> Option<SortValue> {
+        let path = source.path();
+
+        match self {
+            Self::Name => Some(name_value(path)),
+            Self::Modified => fs::metadata(path)
+                .and_then(|m| m.modified())
+                .ok()
+                .map(SortValue::Time),
+            Self::Size => fs::metadata(path).ok().map(|m| SortValue::Number(m.len())),
+            Self::Dimensions => read_dimensions(source).map(pixels_value),
+            Self::DateTaken => read_date_taken(source).map(SortValue::Text),
+            Self::Rating => {
+                let tags = source
+                    .file_path()
+                    .and_then(|path| read_tags(path).ok())
+                    .unwrap_or_default();
+                Some(SortValue::Number(tags.stars().into()))
+            }
+        }
+    }
+}
+
+fn name_value(path: &Path) -> SortValue {
+    let name = path.file_name().unwrap_or(path.as_os_str());
+    SortValue::Name(NaturalName(name.to_string_lossy().into_owned()))
+}
+
+fn pixels_value((width, height): (u32, u32)) -> SortValue {
+    SortValue::Number(u64::from(width) * u64::from(height))
+}
+
+/// Order the keys of images by their values for a sort key
+/// Images without a value are placed last
+pub fn sort_order(mut values: Vec<(Option<SortValue>, ImageKey)>, reverse: bool) -> Vec<ImageKey> {
+    values.sort_by(|(a, _), (b, _)| match (a, b) {
+        (Some(a), Some(b)) if reverse => b.cmp(a),
+        (Some(a), Some(b)) => a.cmp(b),
+        (a, b) => a.is_none().cmp(&b.is_none()),
+    });
+
+    values.into_iter().map(|(_, key)| key).collect()
+}
+
+/// A rule for grouping images into stacks
+#[derive(Debug)]
+pub enum StackRule {
+    /// Group images with the same path other than their extension
+    Stem,
+    /// Group images by keys for their paths
+    /// Images without a key are not stacked
+    Keys(HashMap<PathBuf, String>),
+}
+
+impl StackRule {
+    /// Get the stack key of the image at `path`
+    pub fn key(&self, path: &Path) -> Option<String> {
+        match self {
+            Self::Stem => Some(path.with_extension("").to_string_lossy().into_owned()),
+            Self::Keys(keys) => keys.get(path).cloned(),
+        }
+    }
+}
+
 impl RLens {
-    pub fn init(paths: Vec<PathBuf>, initial_index: usize) -> Self {
-        assert!(!paths.is_empty());
+    pub fn init(sources: Vec<ImageSource>, initial_index: usize, thumbnail_size: u32) -> Self {
+        assert!(!sources.is_empty());
 
-        let images = paths.into_iter().map(Image::new_unloaded).collect();
+        let images = sources.into_iter().map(Image::new_unloaded).collect();
 
         Self {
             mode: Mode::default(),
 
             images,
+            hidden: HashMap::new(),
 
             image_view: ImageView::init(initial_index),
             preload_forward: 0,
@@ -82,7 +291,17 @@ impl RLens {
             image_mode_status_bar: false,
 
             gallery: Gallery::init(),
+
+            collections: vec![None],
+
             save_thumbnails: false,
+            thumbnail_size,
+            hash_images: false,
+            pending_sort: None,
+            sort_generation: 0,
+
+            color_management: true,
+            tone_mapping: ToneMapping::default(),
 
             status_bar: StatusBar::new(),
             status_bar_position: StatusBarPosition::default(),
@@ -125,6 +344,66 @@ impl RLens {
         &self.images[index]
     }
 
+    /// Get the image list
+    pub fn images(&self) -> &[Image] {
+        &self.images
+    }
+
+    /// Get the current index of an image from its key
+    /// `index` is the last known index of the image, which is checked first
+    /// Returns `None` if the image is no longer in the image list
+    pub fn resolve_image(&self, index: usize, key: ImageKey) -> Option<usize> {
+        match self.images.get(index) {
+            Some(image) if image.key() == key => Some(index),
+            _ => self.images.iter().position(|image| image.key() == key),
+        }
+    }
+
+    /// Get the indices of the images with the given path
+    pub fn image_indices(&self, path: &Path) -> Vec<usize> {
+        self.images
+            .iter()
+            .enumerate()
+            .filter(|(_, image)| image.path() == path)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Get the indices of the images whose hashes are within `threshold` bits of the image at `index`
+    /// Ordered from most to least similar
+    /// Only images that have been hashed are considered
+    /// Pre: `index` is valid
+    pub fn similar_images(&self, index: usize, threshold: u32) -> Vec<usize> {
+        let hash = match self.images[index].hash() {
+            Some(hash) => hash,
+            None => return vec![],
+        };
+
+        let mut similar = self
+            .hashed_images()
+            .filter(|&(i, _)| i != index)
+            .map(|(i, other)| (i, hash.distance(&other)))
+            .filter(|&(_, distance)| distance <= threshold)
+            .collect::<Vec<_>>();
+        similar.sort_by_key(|&(_, distance)| distance);
+
+        similar.into_iter().map(|(i, _)| i).collect()
+    }
+
+    /// Group the indices of images whose hashes are within `threshold` bits of each other
+    /// Only images that have been hashed are considered
+    pub fn duplicate_groups(&self, threshold: u32) -> Vec<Vec<usize>> {
+        group_similar(&self.hashed_images().collect::<Vec<_>>(), threshold)
+    }
+
+    /// Iterator over the indices and hashes of the hashed images
+    fn hashed_images(&self) -> impl Iterator<Item = (usize, ImageHash)> + '_ {
+        self.images
+            .iter()
+            .enumerate()
+            .filter_map(|(i, image)| Some((i, image.hash()?)))
+    }
+
     /// Set whether rlens is frozen
     pub fn set_frozen(&mut self, frozen: bool) {
         self.frozen = frozen;
@@ -142,6 +421,590 @@ impl RLens {
         image.forget_unloadable();
     }
 
+    /// Load the image at the index regardless of the decoding limits
+    /// Pre: `index` is valid
+    pub fn ignore_limits(&mut self, index: usize, gfx: &mut Gfx) {
+        self.images[index].set_ignore_limits();
+        self.unload_image(index, gfx);
+    }
+
+    /// Mark the image at the index as out of date with its source
+    /// The current open image and thumbnails are kept until they are replaced,
+    /// and any other full image is unloaded
+    /// Pre: `index` is valid
+    pub fn refresh_image(&mut self, index: usize, gfx: &mut Gfx) {
+        let is_current = index == self.current_open_image();
+        let image = &mut self.images[index];
+
+        if is_current {
+            image.full.mark_stale();
+        } else {
+            image.full.unload_image(gfx);
+        }
+        image.thumbnail.mark_stale();
+
+        image.forget_hash();
+        image.forget_unloadable();
+    }
+
+    /// Add an image to the end of the image list
+    /// Returns the index of the new image
+    pub fn push_image(&mut self, source: ImageSource) -> usize {
+        self.images.push(Image::new_unloaded(source));
+        self.images.len() - 1
+    }
+
+    /// Hide the images that do not pass `keep`
+    /// Any previous filter is cleared first, so `keep` sees every image
+    /// Returns whether the current image changed
+    /// Pre: `keep` passes at least one image
+    pub fn filter(&mut self, keep: impl Fn(&Image) -> bool, gfx: &mut Gfx) -> bool {
+        self.clear_filter(gfx);
+
+        let mut hidden: HashMap<_, Vec<_>> = HashMap::new();
+        let changed = self.rebuild_images(gfx, |images, _| {
+            let mut visible = Vec::new();
+            for image in images {
+                if keep(&image) {
+                    visible.push(image);
+                } else {
+                    hidden
+                        .entry(visible.last().map(Image::key))
+                        .or_default()
+                        .push(image);
+                }
+            }
+            (visible, HashMap::new())
+        });
+
+        // Hidden images are not shown, so their full images and thumbnails are unloaded
+        for image in hidden.values_mut().flatten() {
+            image.full.unload_image(gfx);
+            image.thumbnail.unload_image(gfx);
+        }
+
+        self.hidden = hidden;
+        changed
+    }
+
+    /// Show the images hidden by the filter
+    /// Hidden images return after the visible image they followed, or at the end if it is gone
+    /// The current image and gallery cursor keep their images
+    pub fn clear_filter(&mut self, gfx: &mut Gfx) {
+        if self.hidden.is_empty() {
+            return;
+        }
+
+        let mut hidden = mem::take(&mut self.hidden);
+        self.rebuild_images(gfx, |images, _| {
+            let mut all = hidden.remove(&None).unwrap_or_default();
+            for image in images {
+                let key = image.key();
+                all.push(image);
+                all.extend(hidden.remove(&Some(key)).unwrap_or_default());
+            }
+            all.extend(hidden.into_values().flatten());
+            (all, HashMap::new())
+        });
+    }
+
+    /// Get the index of a visible image in the image list without the filter
+    /// Pre: `index` is valid
+    pub fn absolute_index(&self, index: usize) -> usize {
+        let run_len = |key| self.hidden.get(&key).map_or(0, Vec::len);
+
+        run_len(None)
+            + self.images[..index]
+                .iter()
+                .map(|image| 1 + run_len(Some(image.key())))
+                .sum::<usize>()
+    }
+
+    /// Iterator over the images in the order of the image list without the filter
+    fn all_images(&self) -> impl Iterator<Item = &Image> {
+        all_images(&self.images, &self.hidden)
+    }
+
+    /// Iterator over the images of every collection in order, including hidden images
+    fn all_collection_images(&self) -> impl Iterator<Item = &Image> {
+        self.collections
+            .iter()
+            .flat_map(move |collection| -> Box<dyn Iterator<Item = &Image>> {
+                match collection {
+                    Some(collection) => Box::new(collection.all_images()),
+                    None => Box::new(self.all_images()),
+                }
+            })
+    }
+
+    /// Set whether the image at the index is marked
+    /// Pre: `index` is valid
+    pub fn set_marked(&mut self, index: usize, marked: bool) {
+        self.images[index].set_marked(marked);
+    }
+
+    /// Get the indices of the marked images
+    pub fn marked(&self) -> Vec<usize> {
+        self.images
+            .iter()
+            .enumerate()
+            .filter(|(_, image)| image.is_marked())
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    /// Get the paths of the marked images in every collection, including hidden images and the
+    /// members of stacks
+    pub fn marked_paths(&self) -> Vec<PathBuf> {
+        self.all_collection_images()
+            .filter(|image| image.is_marked())
+            .flat_map(|image| image.stack_members())
+            .map(|source| source.path().to_path_buf())
+            .collect()
+    }
+
+    /// The paths of the images read from the disk, including hidden images and stack members
+    pub fn file_paths(&self) -> impl Iterator<Item = &Path> {
+        self.all_images()
+            .flat_map(|image| image.stack_members())
+            .filter_map(|source| source.file_path())
+    }
+
+    /// The paths of the marked images read from the disk in every collection, including their
+    /// stack members
+    pub fn marked_file_paths(&self) -> impl Iterator<Item = &Path> {
+        self.all_collection_images()
+            .filter(|image| image.is_marked())
+            .flat_map(|image| image.stack_members())
+            .filter_map(|source| source.file_path())
+    }
+
+    /// Update the rating and tags in the sidecars of the image at the index with `update`
+    /// Each member of a stack is updated
+    /// Pre: `index` is valid
+    pub fn update_tags(
+        &mut self,
+        index: usize,
+        update: impl Fn(&mut ImageTags),
+    ) -> Result<(), String> {
+        let image = &mut self.images[index];
+
+        let mut primary_tags = None;
+        for source in image.stack_members() {
+            let path = source.file_path().ok_or_else(|| {
+                format!(
+                    "Image `{}` has no file to store tags for",
+                    source.path().display()
+                )
+            })?;
+            let mut tags = read_tags(path)?;
+            update(&mut tags);
+            write_tags(path, &tags)?;
+            primary_tags.get_or_insert(tags);
+        }
+
+        if let (Some(metadata), Some(tags)) = (image.metadata.loaded_mut(), primary_tags) {
+            metadata.tags = tags;
+        }
+
+        Ok(())
+    }
+
+    /// Move the files of the image at the index with `relocate`, which is given the path of each
+    /// member of the image's stack and returns its new path
+    /// The image keeps its loaded state, and the members moved before any failure take their
+    /// new paths
+    /// Pre: `index` is valid
+    pub fn relocate_image(
+        &mut self,
+        index: usize,
+        relocate: impl Fn(&Path) -> Result<PathBuf, String>,
+    ) -> Result<(), String> {
+        let image = &mut self.images[index];
+        if let Some(source) = image.stack_members().find(|s| s.file_path().is_none()) {
+            return Err(format!(
+                "Image `{}` has no file to move",
+                source.path().display()
+            ));
+        }
+
+        for source in image.stack_members_mut() {
+            let path = source.file_path().expect("Image was not a file");
+            *source = ImageSource::File(relocate(path)?);
+        }
+
+        Ok(())
+    }
+
+    /// Save the orientation of the current open image as displayed to its file, so that the
+    /// rotation and flip of its transform become part of the image
+    /// The image is then reloaded, which resets its transform
+    /// Returns the path of the file
+    pub fn save_orientation(&mut self, gfx: &mut Gfx) -> Result<PathBuf, String> {
+        let index = self.current_open_image();
+        let orientation = self.image_view.current_orientation(&self.images);
+        let transform = self
+            .image_view
+            .transform()
+            .ok_or_else(|| "The current image is not loaded".to_string())?;
+        let orientation = orientation.then_transform(transform).ok_or_else(|| {
+            "The rotation must be a multiple of 90 degrees to be saved".to_string()
+        })?;
+
+        let image = &self.images[index];
+        let path = image
+            .source()
+            .file_path()
+            .ok_or_else(|| format!("Image `{}` has no file to save to", image.path().display()))?
+            .to_path_buf();
+        // The file could not be decoded, so it cannot be re-encoded either
+        if image.metadata.loaded().is_some_and(|m| m.external) {
+            return Err(format!(
+                "Image `{}` was loaded by an external loader and cannot be saved",
+                image.path().display()
+            ));
+        }
+        save_orientation(&path, orientation)?;
+
+        self.unload_image(index, gfx);
+        self.images[index].forget_hash();
+        Ok(path)
+    }
+
+    /// Mark the hidden images with the given path as out of date with their source
+    pub fn refresh_hidden(&mut self, path: &Path, gfx: &mut Gfx) {
+        for image in self.hidden.values_mut().flatten() {
+            if image.path() == path {
+                image.full.unload_image(gfx);
+                image.thumbnail.mark_stale();
+                image.forget_hash();
+                image.forget_unloadable();
+            }
+        }
+    }
+
+    /// Remove the hidden images with the given path
+    pub fn remove_hidden(&mut self, path: &Path, gfx: &mut Gfx) {
+        for run in self.hidden.values_mut() {
+            let (removed, kept) = mem::take(run)
+                .into_iter()
+                .partition::<Vec<_>, _>(|image| image.path() == path);
+            *run = kept;
+            for image in removed {
+                image.into_source(gfx);
+            }
+        }
+        self.hidden.retain(|_, run| !run.is_empty());
+    }
+
+    /// Insert an image into the image list at the index
+    /// The current image and gallery cursor follow their images
+    /// Pre: `index` is at most the number of images
+    pub fn insert_image(&mut self, index: usize, source: ImageSource, gfx: &mut Gfx) {
+        self.rebuild_images(gfx, |mut images, _| {
+            images.insert(index, Image::new_unloaded(source));
+            (images, HashMap::new())
+        });
+    }
+
+    /// Replace the image list with the images from `sources`, clearing the filter
+    /// Images that remain in the list keep their loaded state, and the others are unloaded
+    /// Returns whether the current image changed
+    /// Pre: `sources` is not empty
+    pub fn set_image_list(&mut self, sources: Vec<ImageSource>, gfx: &mut Gfx) -> bool {
+        assert!(!sources.is_empty());
+        self.clear_filter(gfx);
+
+        self.rebuild_images(gfx, |images, gfx| {
+            // The existing images by path, with the first of each path last
+            let mut existing: HashMap<PathBuf, Vec<Image>> = HashMap::new();
+            for image in images.into_iter().rev() {
+                existing
+                    .entry(image.path().to_path_buf())
+                    .or_default()
+                    .push(image);
+            }
+
+            let images = sources
+                .into_iter()
+                .map(|source| {
+                    existing
+                        .get_mut(source.path())
+                        .and_then(|images| images.pop())
+                        .unwrap_or_else(|| Image::new_unloaded(source))
+                })
+                .collect();
+
+            // Unload the dropped images
+            for image in existing.into_values().flatten() {
+                image.into_source(gfx);
+            }
+
+            (images, HashMap::new())
+        })
+    }
+
+    /// Remove the image at the index from the image list
+    /// Returns whether the current image changed (as in `rebuild_images`)
+    /// Pre: `index` is valid, and the image list has more than one image
+    pub fn remove_image(&mut self, index: usize, gfx: &mut Gfx) -> bool {
+        assert!(self.images.len() > 1);
+
+        let current = self.current_open_image();
+
+        // The hidden images after the image now follow the previous image
+        if let Some(run) = self.hidden.remove(&Some(self.images[index].key())) {
+            let previous = index.checked_sub(1).map(|i| self.images[i].key());
+            self.hidden.entry(previous).or_default().extend(run);
+        }
+
+        self.unload_image(index, gfx);
+        self.images.remove(index);
+
+        // Shift the image view and gallery onto the remaining images
+        let view = gfx.window.size();
+        self.image_view.on_remove(index, &self.images, view);
+        let gallery_size = self.gallery_size(view, &gfx.font);
+        self.gallery
+            .on_remove(index, self.images.len(), gallery_size);
+
+        current == index
+    }
+
+    /// Rebuild the image list with `rebuild`, such as to reorder or stack the images
+    /// `rebuild` returns the new image list, and a map from the keys of any images that were
+    /// merged away to the keys of the images they were merged into
+    /// The current image and gallery cursor follow their images
+    /// Returns whether the current image changed
+    pub fn rebuild_images(
+        &mut self,
+        gfx: &mut Gfx,
+        rebuild: impl FnOnce(Vec<Image>, &mut Gfx) -> (Vec<Image>, HashMap<ImageKey, ImageKey>),
+    ) -> bool {
+        let current_key = self.images[self.current_open_image()].key();
+        let cursor_key = self.images[self.gallery_cursor()].key();
+
+        let images = mem::take(&mut self.images);
+        let (images, merged_into) = rebuild(images, gfx);
+        assert!(!images.is_empty());
+        self.images = images;
+
+        // Find the new index of an image, following it if it was merged
+        // Returns the index, and whether it is still the same image
+        let follow = |key: ImageKey| {
+            let merged = merged_into.get(&key);
+            let target = merged.copied().unwrap_or(key);
+            let index = self.images.iter().position(|image| image.key() == target);
+            (index.unwrap_or(0), index.is_some() && merged.is_none())
+        };
+
+        let view = gfx.window.size();
+        let (current, same) = follow(current_key);
+        let (cursor, _) = follow(cursor_key);
+        self.image_view
+            .on_rebuild(current, same, &self.images, view);
+        let gallery_size = self.gallery_size(view, &gfx.font);
+        self.gallery
+            .on_rebuild(cursor, self.images.len(), gallery_size);
+
+        !same
+    }
+
+    /// Sort the image list by `key`
+    /// Images without a value for the key are placed last
+    /// If any values have to be read from the images' sources, they are read by the image loader
+    /// and the image list is sorted once it responds (see `finish_sort`)
+    pub fn sort(&mut self, key: SortKey, reverse: bool, gfx: &mut Gfx) {
+        self.cancel_sort();
+
+        let images = self
+            .images
+            .iter()
+            .map(|image| SortImage {
+                key: image.key(),
+                source: image.source().clone(),
+                value: key.known_value(image),
+            })
+            .collect::<Vec<_>>();
+
+        if images.iter().all(|image| image.value.is_some()) {
+            let values = images
+                .into_iter()
+                .map(|image| (image.value, image.key))
+                .collect();
+            self.apply_order(&sort_order(values, reverse), gfx);
+        } else {
+            self.pending_sort = Some(SortRequest {
+                key,
+                reverse,
+                images,
+                generation: self.sort_generation,
+            });
+        }
+    }
+
+    /// Sort the image list by the order from the image loader
+    /// Returns whether the order was applied, which it is not if the sort was superseded
+    pub fn finish_sort(&mut self, response: SortResponse, gfx: &mut Gfx) -> bool {
+        if response.generation != self.sort_generation {
+            return false;
+        }
+
+        self.apply_order(&response.order, gfx);
+        true
+    }
+
+    /// Reorder the image list to follow the keys of `order`
+    /// Images missing from `order` are placed last, in their current order
+    pub fn reorder(&mut self, order: &[ImageKey], gfx: &mut Gfx) {
+        self.cancel_sort();
+        self.apply_order(order, gfx);
+    }
+
+    /// Drop any sort that is waiting for the image loader
+    fn cancel_sort(&mut self) {
+        self.pending_sort = None;
+        self.sort_generation += 1;
+    }
+
+    /// Reorder the image list to follow the keys of `order`
+    /// Images missing from `order` are placed last, in their current order
+    fn apply_order(&mut self, order: &[ImageKey], gfx: &mut Gfx) {
+        let positions = order
+            .iter()
+            .enumerate()
+            .map(|(i, &key)| (key, i))
+            .collect::<HashMap<_, _>>();
+
+        self.rebuild_images(gfx, |mut images, _| {
+            images.sort_by_key(|image| positions.get(&image.key()).copied().unwrap_or(usize::MAX));
+            (images, HashMap::new())
+        });
+    }
+
+    /// Group the images into stacks by the keys from `key`
+    /// Images with the same key are collapsed into a single stack at the position of the first
+    /// Existing stacks are split before grouping
+    pub fn stack(&mut self, key: impl Fn(&Path) -> Option<String>, gfx: &mut Gfx) {
+        self.unstack(gfx);
+
+        self.rebuild_images(gfx, |images, gfx| {
+            // Group the images by their keys
+            let mut groups: Vec<Vec<Image>> = Vec::new();
+            let mut group_of_key: HashMap<String, usize> = HashMap::new();
+            for image in images {
+                match key(image.path()) {
+                    Some(key) => match group_of_key.entry(key) {
+                        Entry::Occupied(entry) => groups[*entry.get()].push(image),
+                        Entry::Vacant(entry) => {
+                            entry.insert(groups.len());
+                            groups.push(vec![image]);
+                        }
+                    },
+                    None => groups.push(vec![image]),
+                }
+            }
+
+            // Collapse each group into its primary member
+            let mut merged_into = HashMap::new();
+            let images = groups
+                .into_iter()
+                .map(|mut group| {
+                    let mut primary = group.remove(primary_member(&group));
+                    for member in group {
+                        // The stack is marked if any of its members are
+                        if member.is_marked() {
+                            primary.set_marked(true);
+                        }
+                        merged_into.insert(member.key(), primary.key());
+                        primary.add_to_stack(member.into_source(gfx));
+                    }
+                    primary
+                })
+                .collect();
+
+            (images, merged_into)
+        });
+    }
+
+    /// Split all stacks into their members
+    /// The primary member takes the position of the stack, followed by the other members
+    pub fn unstack(&mut self, gfx: &mut Gfx) {
+        if self.images.iter().all(|image| image.stack_size() == 1) {
+            return;
+        }
+
+        self.rebuild_images(gfx, |images, _| {
+            let images = images
+                .into_iter()
+                .flat_map(|mut image| {
+                    let members = image.take_stack();
+                    let marked = image.is_marked();
+                    iter::once(image).chain(members.into_iter().map(move |source| {
+                        let mut member = Image::new_unloaded(source);
+                        member.set_marked(marked);
+                        member
+                    }))
+                })
+                .collect();
+
+            (images, HashMap::new())
+        });
+    }
+
+    /// Make the next member of the image's stack its primary member
+    /// Returns whether the primary member changed
+    /// Pre: `index` is valid
+    pub fn stack_next(&mut self, index: usize, gfx: &mut Gfx) -> bool {
+        if !self.images[index].rotate_stack() {
+            return false;
+        }
+
+        // Discard the state of the previous member
+        self.unload_image(index, gfx);
+        let image = &mut self.images[index];
+        image.metadata.unload();
+        image.forget_hash();
+
+        true
+    }
+
+    /// Remove the members of the image's stack that satisfy `remove`
+    /// Returns whether the primary member changed
+    /// Pre: `index` is valid, and some member is kept
+    pub fn remove_stack_members(
+        &mut self,
+        index: usize,
+        remove: impl Fn(&ImageSource) -> bool,
+        gfx: &mut Gfx,
+    ) -> bool {
+        if !self.images[index].remove_stack_members(remove) {
+            return false;
+        }
+
+        // Discard the state of the previous member
+        self.unload_image(index, gfx);
+        let image = &mut self.images[index];
+        image.metadata.unload();
+        image.forget_hash();
+
+        true
+    }
+
+    /// Mark all full images as out of date
+    /// The current open image is kept until it is replaced, and other full images are unloaded
+    pub fn refresh_full_images(&mut self, gfx: &mut Gfx) {
+        let current = self.current_open_image();
+
+        for (index, image) in self.images.iter_mut().enumerate() {
+            if index == current {
+                image.full.mark_stale();
+            } else {
+                image.full.unload_image(gfx);
+            }
+        }
+    }
+
     // === Image view ===
 
     /// Get the current image open in the image view
@@ -178,6 +1041,13 @@ impl RLens {
         &mut self.image_mode_status_bar
     }
 
+    /// Set whether images are oriented by their EXIF orientation
+    pub fn set_auto_orient(&mut self, auto_orient: bool, view: Size) {
+        self.image_view
+            .set_auto_orient(auto_orient, &self.images, view);
+        self.gallery.set_auto_orient(auto_orient);
+    }
+
     pub fn set_preload_range(&mut self, forward: usize, backward: usize) {
         self.preload_forward = forward;
         self.preload_backward = backward;
@@ -205,6 +1075,10 @@ impl RLens {
         self.save_thumbnails = save;
     }
 
+    pub fn set_hash_images(&mut self, enabled: bool) {
+        self.hash_images = enabled;
+    }
+
     pub fn set_gallery_tile_width(&mut self, width: f32) {
         self.gallery.set_tile_width(width);
     }
@@ -218,6 +1092,189 @@ impl RLens {
         self.segment_bounds(view, font).negative_bar.size()
     }
 
+    // === Collections ===
+
+    /// Get the number of collections
+    pub fn total_collections(&self) -> usize {
+        self.collections.len()
+    }
+
+    /// Get the index of the active collection
+    pub fn active_collection(&self) -> usize {
+        self.collections
+            .iter()
+            .position(Option::is_none)
+            .expect("There was no active collection")
+    }
+
+    /// Add a collection of the images from `sources` after the other collections
+    /// Returns the index of the new collection
+    /// Pre: `sources` is not empty
+    pub fn add_collection(&mut self, sources: Vec<ImageSource>) -> usize {
+        self.collections.push(Some(Collection::new(sources)));
+        self.collections.len() - 1
+    }
+
+    /// Make the collection at the index active
+    /// The previous collection keeps its state, and its full images and thumbnails outside the
+    /// load range are unloaded
+    /// Pre: `index` is valid
+    pub fn switch_collection(&mut self, index: usize, gfx: &mut Gfx) {
+        let active = self.active_collection();
+        if index == active {
+            return;
+        }
+
+        self.unload_images(gfx);
+        let full_images = self
+            .images
+            .iter_mut()
+            .chain(self.hidden.values_mut().flatten())
+            .filter_map(|image| image.full.unload());
+        for loaded in full_images {
+            loaded.unload(gfx);
+        }
+
+        let mut collection = self.collections[index]
+            .take()
+            .expect("Inactive collection was missing");
+        mem::swap(&mut self.images, &mut collection.images);
+        mem::swap(&mut self.hidden, &mut collection.hidden);
+
+        let view = gfx.window.size();
+        self.image_view
+            .swap_state(&mut collection.image_view, &self.images, view);
+        let gallery_size = self.gallery_size(view, &gfx.font);
+        self.gallery
+            .swap_position(&mut collection.gallery, gallery_size);
+
+        self.collections[active] = Some(collection);
+    }
+
+    /// Close the collection at the index, unloading its images
+    /// If it is active, the next collection (or the previous if it is the last) becomes active
+    /// Pre: `index` is valid, and there is more than one collection
+    pub fn close_collection(&mut self, index: usize, gfx: &mut Gfx) {
+        assert!(self.collections.len() > 1);
+
+        if index == self.active_collection() {
+            let next = if index + 1 < self.collections.len() {
+                index + 1
+            } else {
+                index - 1
+            };
+            self.switch_collection(next, gfx);
+        }
+
+        self.collections
+            .remove(index)
+            .expect("Inactive collection was missing")
+            .unload(gfx);
+    }
+
+    // === Sessions ===
+
+    /// Save the state of rlens as a session
+    pub fn session(&mut self) -> Session {
+        let transform = self
+            .image_view
+            .transform()
+            .map(|t| SessionTransform::save(t));
+        let scaling = *self.image_view.scaling();
+        let align = *self.image_view.align();
+
+        let keys = [
+            self.current_open_image(),
+            self.gallery_cursor(),
+            self.gallery.anchor(),
+        ]
+        .map(|index| self.images[index].key());
+
+        let images = self
+            .all_images()
+            .filter(|image| image.source().file_path().is_some())
+            .collect::<Vec<_>>();
+        let collections = self
+            .collections
+            .iter()
+            .flatten()
+            .map(|collection| SessionCollection {
+                images: session_images(collection.all_images()),
+            })
+            .collect();
+        let [index, gallery_cursor, gallery_anchor] = keys.map(|key| {
+            images
+                .iter()
+                .position(|image| image.key() == key)
+                .unwrap_or(0)
+        });
+
+        Session {
+            mode: self.mode,
+            index,
+            gallery_cursor,
+            gallery_anchor,
+            scaling,
+            preload_forward: self.preload_forward,
+            preload_backward: self.preload_backward,
+            align,
+            transform,
+            images: session_images(images),
+            collection: self.active_collection(),
+            collections,
+        }
+    }
+
+    /// Restore the state of rlens from a session, clearing the filter
+    /// Images that are already in the image list keep their loaded state, and the other
+    /// collections are replaced
+    /// Pre: the session has images, and its indices are valid
+    pub fn restore_session(&mut self, session: &Session, gfx: &mut Gfx) {
+        let sources = session
+            .paths()
+            .map(|path| ImageSource::File(path.to_path_buf()))
+            .collect();
+        self.set_image_list(sources, gfx);
+        restore_images(&mut self.images, &session.images);
+
+        for collection in mem::take(&mut self.collections).into_iter().flatten() {
+            collection.unload(gfx);
+        }
+        self.collections = session
+            .collections
+            .iter()
+            .map(|saved| {
+                let sources = saved
+                    .images
+                    .iter()
+                    .map(|image| ImageSource::File(image.path.clone()))
+                    .collect();
+                let mut collection = Collection::new(sources);
+                restore_images(&mut collection.images, &saved.images);
+                Some(collection)
+            })
+            .collect();
+        self.collections.insert(session.collection, None);
+
+        self.mode = session.mode;
+        *self.image_view.scaling() = session.scaling;
+        *self.image_view.align() = session.align;
+        self.set_preload_range(session.preload_forward, session.preload_backward);
+
+        let view = gfx.window.size();
+        let transform = session
+            .transform
+            .as_ref()
+            .and_then(SessionTransform::restore);
+        self.image_view
+            .restore(session.index, transform, &self.images, view);
+
+        let anchor = session.gallery_anchor.min(self.images.len() - 1);
+        let gallery_size = self.gallery_size(view, &gfx.font);
+        self.gallery
+            .restore(session.gallery_cursor, anchor, gallery_size);
+    }
+
     // === Status bar ===
 
     /// Set the text of the status bar
@@ -255,6 +1312,22 @@ impl RLens {
         self.gallery.set_border_color(color);
     }
 
+    pub fn set_gallery_error_color(&mut self, color: Color) {
+        self.gallery.set_error_color(color);
+    }
+
+    pub fn set_gallery_badge_color(&mut self, color: Color) {
+        self.gallery.set_badge_color(color);
+    }
+
+    pub fn set_gallery_mark_color(&mut self, color: Color) {
+        self.gallery.set_mark_color(color);
+    }
+
+    pub fn set_gallery_rating_color(&mut self, color: Color) {
+        self.gallery.set_rating_color(color);
+    }
+
     pub fn set_status_bar_color(&mut self, color: Color) {
         self.status_bar.set_bg(color);
     }
@@ -264,33 +1337,54 @@ impl RLens {
 
 impl RLens {
     /// Set a full image or thumbnail as loaded
+    /// Any stale image that is replaced is unloaded
     /// Returns whether a redraw is required and whether the current image was loaded
     pub fn set_loaded(
         &mut self,
         type_: ImageType,
         index: usize,
-        loaded_image: LoadedImage,
+        mut loaded_image: LoadedImage,
         metadata: Metadata,
-        view: Size,
+        gfx: &mut Gfx,
     ) -> (bool, bool) {
-        let image_size = loaded_image.size();
+        // Catch up with any change to the tone mapping since the request was made
+        loaded_image
+            .set_tone_mapping(self.tone_mapping, gfx)
+            .print_err()
+            .ok();
 
         // Update the image list
-        {
+        let reloaded = {
             let image = &mut self.images[index];
 
             let load_state = match type_ {
                 ImageType::Full => &mut image.full,
-                ImageType::Thumbnail => &mut image.thumbnail,
+                ImageType::Thumbnail(size) => {
+                    // A loaded thumbnail is replaced when a larger size is needed
+                    image.thumbnail.mark_stale();
+                    image.thumbnail_size = size;
+                    &mut image.thumbnail
+                }
             };
-            load_state.load(loaded_image);
+            let stale = load_state.load(loaded_image);
+            let reloaded = stale.is_some();
+            if let Some(stale) = stale {
+                stale.unload(gfx);
+            }
 
             image.metadata.set_loaded(metadata);
-        }
+
+            reloaded
+        };
 
         // Reset image view if we loaded the current open image
-        if type_ == ImageType::Full && self.current_open_image() == index {
-            self.image_view.reset_with_size(image_size, view);
+        // The transform is kept when reloading a stale image
+        if type_ == ImageType::Full
+            && self.current_open_image() == index
+            && (!reloaded || self.image_view.transform().is_none())
+        {
+            self.image_view
+                .reset_if_loaded(&self.images, gfx.window.size());
         }
 
         // Determine whether to redraw and whether the current image was loaded
@@ -300,7 +1394,7 @@ impl RLens {
                 let redraw = loaded_current;
                 (redraw, loaded_current)
             }
-            ImageType::Thumbnail if self.mode == Mode::Gallery => {
+            ImageType::Thumbnail(_) if self.mode == Mode::Gallery => {
                 let loaded_current = self.gallery_cursor() == index;
                 let redraw = true;
                 (redraw, loaded_current)
@@ -309,21 +1403,92 @@ impl RLens {
         }
     }
 
-    /// Mark an image as unloadable
-    pub fn mark_unloadable(&mut self, index: usize) {
-        self.images[index].mark_unloadable();
+    /// Mark an image as unloadable due to `error`
+    /// Pre: `index` is valid
+    pub fn mark_unloadable(&mut self, index: usize, error: LoadError) {
+        self.images[index].mark_unloadable(error);
+    }
+
+    /// Set the perceptual hash of an image
+    /// Pre: `index` is valid
+    pub fn set_hash(&mut self, index: usize, hash: ImageHash) {
+        self.images[index].set_hash(hash);
+    }
+
+    pub fn color_management(&self) -> bool {
+        self.color_management
+    }
+
+    pub fn set_color_management(&mut self, enabled: bool) {
+        self.color_management = enabled;
+    }
+
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.tone_mapping
+    }
+
+    /// Set the tone mapping, and apply it to the loaded full images
+    /// Returns whether the current open image was updated
+    pub fn set_tone_mapping(&mut self, tone_mapping: ToneMapping, gfx: &mut Gfx) -> bool {
+        self.tone_mapping = tone_mapping;
+
+        let current = self.current_open_image();
+        let mut updated_current = false;
+
+        for (index, image) in self.images.iter_mut().enumerate() {
+            if let Some(loaded) = image.full.loaded_mut() {
+                let updated = loaded
+                    .set_tone_mapping(tone_mapping, gfx)
+                    .print_err()
+                    .unwrap_or(false);
+                updated_current |= updated && index == current;
+            }
+        }
+
+        updated_current
     }
 
     /// Poll for a load request
     /// Returns `None` if all images within the load range are already loaded
-    pub fn poll_loads(&self, view: Size, font: &Font) -> Option<LoadRequest> {
+    pub fn poll_loads(&mut self, view: Size, font: &Font) -> Option<LoadRequest> {
+        // Sorts take precedence, as the image list may be reordered by them
+        if let Some(sort) = self.pending_sort.take() {
+            return Some(LoadRequest::Sort(sort));
+        }
+
         // Poll for the appropriate request type
-        match self.mode {
+        let request = match self.mode {
             Mode::Image => self.poll_full_load().map(LoadRequest::Full),
             Mode::Gallery => self
                 .poll_thumbnail_load(view, font)
                 .map(LoadRequest::Thumbnail),
+        };
+
+        // Hash images in the background once the visible images are loaded
+        request.or_else(|| self.poll_hash().map(LoadRequest::Hash))
+    }
+
+    /// Poll for a hash request
+    fn poll_hash(&self) -> Option<HashRequest> {
+        if !self.hash_images {
+            return None;
         }
+
+        self.image_offsets(self.current_image())
+            // Filter to images that need hashing and are not unloadable
+            .filter(|&(_, _, image)| image.hash().is_none() && !image.is_unloadable())
+            // Select the closest candidate
+            .min_by_key(|(_, offset, _)| offset.key())
+            // Make the request for this candidate
+            .map(|(index, _, image)| HashRequest {
+                thumbnail: ThumbnailRequest::for_image(
+                    index,
+                    image,
+                    self.thumbnail_size,
+                    self.save_thumbnails,
+                    self.color_management,
+                ),
+            })
     }
 
     /// Poll for a full load request
@@ -331,32 +1496,47 @@ impl RLens {
         self.image_offsets(self.current_open_image())
             // Filter to images within our load range
             .filter(|(_, offset, _)| offset.in_range(self.preload_forward, self.preload_backward))
-            // Filter to images that are unloaded and not unloadable
-            .filter(|&(_, _, image)| !image.full.is_loaded() && !image.is_unloadable())
+            // Filter to images that need loading and are not unloadable
+            .filter(|&(_, _, image)| image.full.needs_load() && !image.is_unloadable())
             // Select the closest candidate
             .min_by_key(|(_, offset, _)| offset.key())
             // Make the request for this candidate
-            .map(|(index, _, image)| FullRequest::for_image(index, image))
+            .map(|(index, _, image)| {
+                FullRequest::for_image(index, image, self.color_management, self.tone_mapping)
+            })
     }
 
     /// Poll for a thumbnail load request
     fn poll_thumbnail_load(&self, view: Size, font: &Font) -> Option<ThumbnailRequest> {
+        let gallery_size = self.gallery_size(view, font);
+
         // Load range
-        let (first, tiles) = self
-            .gallery
-            .load_range(self.gallery_size(view, font))
-            .unwrap_or((0, 0));
+        let (first, tiles) = self.gallery.load_range(gallery_size).unwrap_or((0, 0));
+
+        // The size of thumbnail that covers the tiles
+        let size = thumbnail_bucket(
+            self.thumbnail_size,
+            self.gallery.thumbnail_size(gallery_size).unwrap_or(0),
+        );
+        let needs_load =
+            |image: &Image| image.thumbnail.needs_load() || image.thumbnail_size < size;
 
         self.image_offsets(first)
             // Filter to images within our load range
             .filter(|(_, offset, _)| offset.in_range(tiles, 0))
-            // Filter to images that are unloaded and not unloadable
-            .filter(|&(_, _, image)| !image.thumbnail.is_loaded() && !image.is_unloadable())
+            // Filter to images that need loading and are not unloadable
+            .filter(|&(_, _, image)| needs_load(image) && !image.is_unloadable())
             // Select the closest candidate
             .min_by_key(|(_, offset, _)| offset.key())
             // Make the request for this candidate
             .map(|(index, _, image)| {
-                ThumbnailRequest::for_image(index, image, self.save_thumbnails)
+                ThumbnailRequest::for_image(
+                    index,
+                    image,
+                    size,
+                    self.save_thumbnails,
+                    self.color_management,
+                )
             })
     }
 
@@ -422,6 +1602,87 @@ impl RLens {
     }
 }
 
+/// Iterator over `images` and the runs in `hidden` in the order of the image list without the
+/// filter
+fn all_images<'a>(
+    images: &'a [Image],
+    hidden: &'a HashMap<Option<ImageKey>, Vec<Image>>,
+) -> impl Iterator<Item = &'a Image> {
+    let run = |key| hidden.get(&key).into_iter().flatten();
+    let visible_keys = images
+        .iter()
+        .map(|image| Some(image.key()))
+        .collect::<HashSet<_>>();
+
+    // Runs that follow images that are gone come last
+    let orphans = hidden
+        .iter()
+        .filter(move |(key, _)| key.is_some() && !visible_keys.contains(key))
+        .flat_map(|(_, images)| images);
+
+    run(None)
+        .chain(
+            images
+                .iter()
+                .flat_map(move |image| iter::once(image).chain(run(Some(image.key())))),
+        )
+        .chain(orphans)
+}
+
+/// Save `images` for a session
+fn session_images<'a>(images: impl IntoIterator<Item = &'a Image>) -> Vec<SessionImage> {
+    images
+        .into_iter()
+        .filter(|image| image.source().file_path().is_some())
+        .map(|image| SessionImage {
+            path: absolute_path(image.path()),
+            stack: image
+                .stack_members()
+                .skip(1)
+                .filter_map(ImageSource::file_path)
+                .map(absolute_path)
+                .collect(),
+            marked: image.is_marked(),
+        })
+        .collect()
+}
+
+/// Restore the stacks and marks of `images` from their saved images
+fn restore_images(images: &mut [Image], saved: &[SessionImage]) {
+    for (image, saved) in images.iter_mut().zip(saved) {
+        image.take_stack();
+        for path in &saved.stack {
+            image.add_to_stack(ImageSource::File(path.clone()));
+        }
+        image.set_marked(saved.marked);
+    }
+}
+
+/// Select the primary member of a stack from its images
+/// This is the first image in a format that rlens can decode, or the first image otherwise
+/// (e.g. the JPEG of a RAW+JPEG pair)
+/// Pre: `group` is non-empty
+fn primary_member(group: &[Image]) -> usize {
+    group
+        .iter()
+        .position(|image| ::image::ImageFormat::from_path(image.path()).is_ok())
+        .unwrap_or(0)
+}
+
+/// Number of sizes that thumbnails are requested at
+/// Each size is double the previous
+const THUMBNAIL_BUCKETS: u32 = 4;
+
+/// Select the smallest thumbnail size that is at least `needed`, starting from `base`
+/// The largest size is selected if none are large enough
+fn thumbnail_bucket(base: u32, needed: u32) -> u32 {
+    let bucket = |i: u32| base.saturating_mul(1 << i);
+    (0..THUMBNAIL_BUCKETS)
+        .map(bucket)
+        .find(|&size| size >= needed)
+        .unwrap_or_else(|| bucket(THUMBNAIL_BUCKETS - 1))
+}
+
 // === Drawing ===
 
 impl RLens {
@@ -0,0 +1,134 @@
+//! Module for sessions, which save the state of rlens to be restored later
+
+use crate::image_transform::{Align, ImageTransform, Scaling};
+use crate::rlens::Mode;
+use crate::util::write_atomically;
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// The saved state of rlens
+/// Indices are into `images`
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Session {
+    pub mode: Mode,
+    /// The index of the current open image
+    pub index: usize,
+    /// The index of the gallery cursor
+    pub gallery_cursor: usize,
+    /// The index of the first image in the gallery's view
+    pub gallery_anchor: usize,
+    pub scaling: Scaling,
+    pub preload_forward: usize,
+    pub preload_backward: usize,
+    pub align: Align,
+    /// The transform on the current open image, if it was loaded
+    pub transform: Option<SessionTransform>,
+    /// The image list, including images hidden by the filter
+    /// Images read from stdin or named pipes are not saved
+    pub images: Vec<SessionImage>,
+    /// The index of the active collection, whose image list is `images`
+    #[serde(default)]
+    pub collection: usize,
+    /// The inactive collections in order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub collections: Vec<SessionCollection>,
+}
+
+/// A saved inactive collection
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionCollection {
+    /// The image list, as in `Session::images`
+    pub images: Vec<SessionImage>,
+}
+
+/// An image in a saved image list
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionImage {
+    /// The absolute path of the image
+    pub path: PathBuf,
+    /// The paths of the other members of the image's stack
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stack: Vec<PathBuf>,
+    #[serde(default)]
+    pub marked: bool,
+}
+
+/// A saved image transform
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionTransform {
+    pub pan: (f32, f32),
+    pub zoom: f32,
+    pub rotation: f32,
+    pub flip: bool,
+}
+
+impl Session {
+    /// Read a session from the file at `path`
+    pub fn read(path: &Path) -> Result<Self, String> {
+        let session_err = |e: &dyn std::fmt::Display| {
+            format!("Error reading session at `{}`: {}", path.display(), e)
+        };
+
+        let text = fs::read_to_string(path).map_err(|e| session_err(&e))?;
+        let session: Self = toml::from_str(&text).map_err(|e| session_err(&e))?;
+
+        let total = session.images.len();
+        if total == 0 {
+            return Err(session_err(&"The session has no images"));
+        }
+        if session.index >= total || session.gallery_cursor >= total {
+            return Err(session_err(&"An index is out of range"));
+        }
+        if session.collection > session.collections.len() {
+            return Err(session_err(&"The active collection is out of range"));
+        }
+        if session.collections.iter().any(|c| c.images.is_empty()) {
+            return Err(session_err(&"A collection has no images"));
+        }
+
+        Ok(session)
+    }
+
+    /// Write the session to the file at `path`
+    pub fn write(&self, path: &Path) -> Result<(), String> {
+        let text = toml::to_string(self)
+            .map_err(|e| format!("Error: Failed to serialize the session: {}", e))?;
+
+        write_atomically(path, |temp_path| {
+            fs::write(temp_path, text).map_err(|e| e.to_string())
+        })
+    }
+
+    /// The paths of the primary members of the images
+    pub fn paths(&self) -> impl Iterator<Item = &Path> {
+        self.images.iter().map(|image| image.path.as_path())
+    }
+}
+
+impl SessionTransform {
+    pub fn save(transform: &ImageTransform) -> Self {
+        Self {
+            pan: transform.get_pan(),
+            zoom: transform.get_zoom(),
+            rotation: transform.get_rotation(),
+            flip: transform.get_flip(),
+        }
+    }
+
+    /// The saved transform
+    /// `None` if the zoom is not positive
+    pub fn restore(&self) -> Option<ImageTransform> {
+        if self.zoom.is_nan() || self.zoom <= 0.0 {
+            return None;
+        }
+
+        let mut transform = ImageTransform::default();
+        transform.set_pan(self.pan);
+        transform.set_zoom(self.zoom);
+        transform.set_rotation(self.rotation);
+        transform.set_flip(self.flip);
+        Some(transform)
+    }
+}
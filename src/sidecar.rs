@@ -0,0 +1,226 @@
+//! Module for XMP sidecars, which store the ratings and tags of images
+//!
+//! The sidecar of `image.jpg` is `image.jpg.xmp` (as written by darktable).
+//! Existing sidecars are edited in place, so that other tools' data in them is kept.
+
+use crate::util::write_atomically;
+
+use roxmltree::{Document, Node};
+use std::fs;
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+const RDF_NS: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const XMP_NS: &str = "http://ns.adobe.com/xap/1.0/";
+const DC_NS: &str = "http://purl.org/dc/elements/1.1/";
+
+/// The highest rating
+pub const MAX_RATING: u8 = 5;
+/// The rating of rejected images
+const REJECTED_RATING: i32 = -1;
+
+/// The sidecar that new sidecars start from
+const EMPTY_SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="">
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+"#;
+
+/// The rating and tags of an image
+#[derive(Clone, Default, PartialEq, Debug)]
+pub struct ImageTags {
+    /// The rating as stored, from `-1` (rejected) through `0` (unrated) to `MAX_RATING`
+    pub rating: i32,
+    /// The keyword tags
+    pub tags: Vec<String>,
+}
+
+impl ImageTags {
+    /// The star rating, from `0` (unrated or rejected) to `MAX_RATING`
+    pub fn stars(&self) -> u8 {
+        self.rating.clamp(0, MAX_RATING.into()) as u8
+    }
+}
+
+/// The path of the sidecar of the image at `path`
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(".xmp");
+    PathBuf::from(name)
+}
+
+/// Read the rating and tags of the image at `path` from its sidecar
+/// Images without a sidecar are unrated and untagged
+pub fn read_tags(path: &Path) -> Result<ImageTags, String> {
+    let sidecar = sidecar_path(path);
+    let text = match fs::read_to_string(&sidecar) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(ImageTags::default()),
+        Err(e) => return Err(sidecar_error(&sidecar, e)),
+    };
+
+    let doc = Document::parse(&text).map_err(|e| sidecar_error(&sidecar, e))?;
+    Ok(parse_tags(&doc))
+}
+
+/// Parse the rating and tags of a sidecar
+fn parse_tags(doc: &Document) -> ImageTags {
+    let mut tags = ImageTags::default();
+    for description in descriptions(doc) {
+        let rating = description.attribute((XMP_NS, "Rating")).or_else(|| {
+            description
+                .children()
+                .find(|child| child.has_tag_name((XMP_NS, "Rating")))
+                .and_then(|child| child.text())
+        });
+        if let Some(rating) = rating.and_then(|r| r.trim().parse::<i32>().ok()) {
+            // Negative ratings mark rejected images
+            tags.rating = rating.clamp(REJECTED_RATING, MAX_RATING.into());
+        }
+
+        let subjects = description
+            .children()
+            .filter(|child| child.has_tag_name((DC_NS, "subject")));
+        for subject in subjects {
+            let items = subject
+                .descendants()
+                .filter(|node| node.has_tag_name((RDF_NS, "li")))
+                .filter_map(|node| node.text());
+            tags.tags.extend(items.map(str::to_string));
+        }
+    }
+
+    tags
+}
+
+/// Write the rating and tags of the image at `path` to its sidecar
+pub fn write_tags(path: &Path, tags: &ImageTags) -> Result<(), String> {
+    let sidecar = sidecar_path(path);
+    let text = match fs::read_to_string(&sidecar) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => EMPTY_SIDECAR.to_string(),
+        Err(e) => return Err(sidecar_error(&sidecar, e)),
+    };
+
+    let text = update_sidecar(&text, tags).map_err(|e| sidecar_error(&sidecar, e))?;
+
+    write_atomically(&sidecar, |temp_path| {
+        fs::write(temp_path, text).map_err(|e| e.to_string())
+    })
+}
+
+/// Replace the rating and tags in the text of a sidecar
+/// Only the ones that differ from the sidecar's are rewritten
+fn update_sidecar(text: &str, tags: &ImageTags) -> Result<String, String> {
+    let doc = Document::parse(text).map_err(|e| e.to_string())?;
+
+    let current = parse_tags(&doc);
+    let rating = (tags.rating != current.rating).then_some(tags.rating);
+    let subjects = (tags.tags != current.tags).then_some(tags.tags.as_slice());
+    if rating.is_none() && subjects.is_none() {
+        return Ok(text.to_string());
+    }
+
+    // Edits to the text as ranges and their replacements
+    let mut edits: Vec<(Range<usize>, String)> = Vec::new();
+
+    // Remove the existing rating and tags that are replaced
+    for description in descriptions(&doc) {
+        let ratings = description
+            .attributes()
+            .filter(|attr| attr.namespace() == Some(XMP_NS) && attr.name() == "Rating")
+            .map(|attr| attr.range())
+            .filter(|_| rating.is_some());
+        let elements = description
+            .children()
+            .filter(|child| {
+                (rating.is_some() && child.has_tag_name((XMP_NS, "Rating")))
+                    || (subjects.is_some() && child.has_tag_name((DC_NS, "subject")))
+            })
+            .map(|child| child.range());
+        edits.extend(ratings.chain(elements).map(|range| (range, String::new())));
+    }
+
+    // Add the new rating and tags to the end of the first description
+    let description = descriptions(&doc)
+        .next()
+        .ok_or_else(|| "No rdf:Description element".to_string())?;
+    let elements = tag_elements(rating, subjects);
+    let range = description.range();
+    let source = &text[range.clone()];
+    if source.ends_with("/>") {
+        // Expand the empty element to hold the new elements
+        let name_len = source[1..]
+            .find(|c: char| c.is_whitespace() || c == '/')
+            .unwrap_or_default();
+        let name = &source[1..1 + name_len];
+        edits.push((
+            range.end - 2..range.end,
+            format!(">{}</{}>", elements, name),
+        ));
+    } else {
+        let end_tag = text[..range.end]
+            .rfind("</")
+            .ok_or_else(|| "Malformed rdf:Description element".to_string())?;
+        edits.push((end_tag..end_tag, elements));
+    }
+
+    // Apply the edits from the end so that the earlier ranges stay valid
+    edits.sort_by_key(|(range, _)| (range.start, range.end));
+    let mut text = text.to_string();
+    for (range, replacement) in edits.into_iter().rev() {
+        text.replace_range(range, &replacement);
+    }
+
+    Ok(text)
+}
+
+/// The elements that hold the rating and tags, if given
+/// Each declares its namespace, as the sidecar may use other prefixes
+fn tag_elements(rating: Option<i32>, tags: Option<&[String]>) -> String {
+    let mut elements = String::new();
+
+    if let Some(rating) = rating.filter(|&rating| rating != 0) {
+        elements.push_str(&format!(
+            "<xmp:Rating xmlns:xmp=\"{}\">{}</xmp:Rating>",
+            XMP_NS, rating
+        ));
+    }
+
+    if let Some(tags) = tags.filter(|tags| !tags.is_empty()) {
+        let items = tags
+            .iter()
+            .map(|tag| format!("<rdf:li>{}</rdf:li>", escape(tag)))
+            .collect::<String>();
+        elements.push_str(&format!(
+            "<dc:subject xmlns:dc=\"{}\"><rdf:Bag xmlns:rdf=\"{}\">{}</rdf:Bag></dc:subject>",
+            DC_NS, RDF_NS, items
+        ));
+    }
+
+    elements
+}
+
+/// Iterator over the rdf:Description elements of a sidecar
+fn descriptions<'a, 'input>(doc: &'a Document<'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    doc.descendants()
+        .filter(|node| node.has_tag_name((RDF_NS, "Description")))
+}
+
+/// Escape text for XML
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+fn sidecar_error(sidecar: &Path, e: impl std::fmt::Display) -> String {
+    format!(
+        "Error: Failed to read sidecar at `{}`: {}",
+        sidecar.display(),
+        e
+    )
+}
@@ -0,0 +1,141 @@
+//! Module for converting high bit-depth and HDR images into 8 bits for display
+//!
+//! Images with more than 8 bits per channel are tone mapped rather than clamped.
+//! The high precision data of loaded full images is retained, so that they can be tone mapped
+//! again when the settings change without reading the source.
+
+use crate::color::ColorTransform;
+
+use image::{DynamicImage, Rgba32FImage, RgbaImage};
+use std::fmt::{self, Debug};
+
+/// The gamma that integer images are assumed to be encoded with, approximating the sRGB curve
+const ENCODING_GAMMA: f32 = 2.2;
+
+/// Settings for tone mapping
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct ToneMapping {
+    /// Exposure adjustment in stops
+    pub exposure: f32,
+    /// The gamma of the display
+    /// > 0
+    pub gamma: f32,
+    /// Operator for mapping values into the displayable range
+    pub operator: ToneMapOperator,
+}
+
+#[derive(Clone, Copy, PartialEq, Default, Debug)]
+pub enum ToneMapOperator {
+    /// Clamp values into the displayable range
+    #[default]
+    Clamp,
+    /// Reinhard's operator (`x / (1 + x)`)
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve
+    Aces,
+}
+
+/// High precision image data, retained for tone mapping
+pub struct HdrImage {
+    /// The decoded image, in its original precision
+    data: DynamicImage,
+    /// Conversion from the embedded color profile, applied after tone mapping
+    color_transform: Option<ColorTransform>,
+    /// The tone mapping last applied
+    tone_mapping: ToneMapping,
+}
+
+impl Default for ToneMapping {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            gamma: 2.2,
+            operator: ToneMapOperator::default(),
+        }
+    }
+}
+
+impl ToneMapping {
+    /// Map a linear value into the displayable range
+    fn map(&self, linear: f32, scale: f32) -> f32 {
+        let x = linear * scale;
+
+        let mapped = match self.operator {
+            ToneMapOperator::Clamp => x,
+            ToneMapOperator::Reinhard => x / (1.0 + x),
+            ToneMapOperator::Aces => (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14),
+        };
+
+        mapped.clamp(0.0, 1.0).powf(1.0 / self.gamma)
+    }
+}
+
+// Avoid dumping the raw image data
+impl Debug for HdrImage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("HdrImage")
+            .field("color", &self.data.color())
+            .field("dimensions", &(self.data.width(), self.data.height()))
+            .field("tone_mapping", &self.tone_mapping)
+            .finish()
+    }
+}
+
+impl HdrImage {
+    pub fn new(data: DynamicImage, color_transform: Option<ColorTransform>) -> Self {
+        Self {
+            data,
+            color_transform,
+            tone_mapping: ToneMapping::default(),
+        }
+    }
+
+    pub fn tone_mapping(&self) -> ToneMapping {
+        self.tone_mapping
+    }
+
+    /// Tone map the image into RGBA8 for the canvas
+    pub fn render(&mut self, tone_mapping: ToneMapping) -> RgbaImage {
+        self.tone_mapping = tone_mapping;
+
+        let image = DynamicImage::ImageRgba8(tone_map(&self.data, tone_mapping));
+        match &self.color_transform {
+            Some(transform) => transform.apply(image).into_rgba8(),
+            None => image.into_rgba8(),
+        }
+    }
+}
+
+/// Check if an image has more than 8 bits per channel
+pub fn is_high_precision(image: &DynamicImage) -> bool {
+    let color = image.color();
+    color.bytes_per_pixel() > color.channel_count()
+}
+
+/// Tone map an image into RGBA8
+fn tone_map(image: &DynamicImage, tone_mapping: ToneMapping) -> RgbaImage {
+    // Floating point images are linear, whereas integer images are gamma encoded
+    // Integer images are decoded with a fixed gamma, so that the display gamma changes them
+    let linear = matches!(
+        image,
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_)
+    );
+
+    let data: Rgba32FImage = image.to_rgba32f();
+    let scale = tone_mapping.exposure.exp2();
+
+    let mut out = RgbaImage::new(data.width(), data.height());
+    for (src, dst) in data.pixels().zip(out.pixels_mut()) {
+        for c in 0..3 {
+            let value = if linear {
+                src[c]
+            } else {
+                src[c].max(0.0).powf(ENCODING_GAMMA)
+            };
+            dst[c] = (tone_mapping.map(value, scale) * 255.0).round() as u8;
+        }
+        dst[3] = (src[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+    }
+
+    out
+}
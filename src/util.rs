@@ -1,11 +1,13 @@
 //! Module for utility types and functions not belonging to other modules
 
 use md5::{Digest, Md5};
+use std::env;
 use std::error::Error;
 use std::fmt::{Display, Formatter, Result as FmtResult};
-use std::fs::create_dir_all;
+use std::fs::{self, create_dir_all};
 use std::hash::{Hash, Hasher};
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 
 /// Create a directory if it does not exist
 /// Fails if the directory could not be created
@@ -18,6 +20,37 @@ pub fn touch_dir(p: &Path) -> Result<(), String> {
     Ok(())
 }
 
+/// Write a file with `write`, which is given the path to write to
+/// The file is written to a temporary path and then moved into place, so the file at `path` is
+/// never partially written
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&Path) -> Result<(), String>,
+) -> Result<(), String> {
+    let write_err = |e: &dyn std::fmt::Display| {
+        format!("Error: Failed to write file at `{}`: {}", path.display(), e)
+    };
+
+    if let Some(dir) = path.parent() {
+        touch_dir(dir)?;
+    }
+
+    // Each file is only written by one thread, so the pid makes the name unique
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(format!(".{}.tmp", process::id()));
+    let temp_path = path.with_file_name(temp_name);
+
+    let result = write(&temp_path)
+        .map_err(|e| write_err(&e))
+        .and_then(|()| fs::rename(&temp_path, path).map_err(|e| write_err(&e)));
+
+    if result.is_err() {
+        fs::remove_file(&temp_path).ok();
+    }
+
+    result
+}
+
 /// `String` wrapper that implements `Error`
 #[derive(Debug)]
 pub struct StrError(pub String);
@@ -123,6 +156,51 @@ impl Offset {
     }
 }
 
+/// Match a string against a glob pattern
+/// `*` matches any sequence of characters, and `?` matches any single character
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+
+    // Positions in the pattern and string
+    let (mut p, mut i) = (0, 0);
+    // The position of the last `*` in the pattern, and the position in the string it was matched at
+    let mut backtrack = None;
+
+    while i < s.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, i));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == s[i] => {
+                p += 1;
+                i += 1;
+            }
+            _ => {
+                // Mismatch, so extend the last `*` by a character
+                if let Some((star_p, star_i)) = backtrack {
+                    p = star_p + 1;
+                    i = star_i + 1;
+                    backtrack = Some((star_p, star_i + 1));
+                } else {
+                    return false;
+                }
+            }
+        }
+    }
+
+    // Any remaining pattern must only match the empty string
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Make a path absolute, resolving symlinks if the path exists
+pub fn absolute_path(path: &Path) -> PathBuf {
+    path.canonicalize()
+        .or_else(|_| env::current_dir().map(|dir| dir.join(path)))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
 /// Hash the given filepath into a hex string
 /// Pre: `path` is absolute
 pub fn hash_filepath(path: &Path) -> String {
@@ -0,0 +1,209 @@
+//! Module for watching the files of the image list for changes
+//!
+//! The directories containing the images are watched, rather than the files themselves, so that
+//! files replaced by a rename (as is common for atomic saves) continue to be tracked.
+
+use crate::program::{Request, RequestSender};
+use crate::util::PrintErr;
+
+use image::ImageFormat;
+use notify::event::{AccessKind, AccessMode, ModifyKind, RenameMode};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+pub struct Watcher {
+    /// Sender for forwarding file events to the main thread
+    request_tx: RequestSender,
+    /// The underlying watcher
+    /// Present only while watching is enabled
+    watcher: Option<RecommendedWatcher>,
+    /// Map from the canonical paths of watched files to their paths in the image list
+    files: HashMap<PathBuf, PathBuf>,
+    /// The canonical paths of the watched directories
+    dirs: HashSet<PathBuf>,
+    /// Whether new images in the watched directories should be added to the image list,
+    /// and deleted images dropped from it
+    track_directories: bool,
+}
+
+/// A change to a file
+#[derive(Debug)]
+pub struct FileChange {
+    pub kind: FileChangeKind,
+    /// The path of the file
+    /// For images already in the image list, this is their path in the list
+    pub path: PathBuf,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FileChangeKind {
+    /// A file in the image list was written to
+    Modify,
+    /// A new image was created in a watched directory
+    Create,
+    /// A file in the image list was removed
+    Remove,
+}
+
+impl Watcher {
+    pub fn new(request_tx: RequestSender) -> Self {
+        Self {
+            request_tx,
+            watcher: None,
+            files: HashMap::new(),
+            dirs: HashSet::new(),
+            track_directories: false,
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    /// Start watching the files at the given paths
+    /// No effect if already enabled
+    pub fn enable<'a>(&mut self, paths: impl Iterator<Item = &'a Path>) -> Result<(), String> {
+        if self.is_enabled() {
+            return Ok(());
+        }
+
+        let request_tx = self.request_tx.clone();
+        let watcher = notify::recommended_watcher(move |event: notify::Result<Event>| {
+            if let Ok(event) = event.print_err() {
+                request_tx.send(Request::FileEvent(event)).ok();
+            }
+        })
+        .map_err(|e| format!("Failed to create the file watcher: {}", e))?;
+        self.watcher = Some(watcher);
+
+        for path in paths {
+            self.add(path);
+        }
+
+        Ok(())
+    }
+
+    /// Stop watching files
+    pub fn disable(&mut self) {
+        self.watcher = None;
+        self.files.clear();
+        self.dirs.clear();
+    }
+
+    pub fn track_directories(&self) -> bool {
+        self.track_directories
+    }
+
+    pub fn set_track_directories(&mut self, track: bool) {
+        self.track_directories = track;
+    }
+
+    /// Watch the file at `path`
+    /// No effect if watching is disabled
+    pub fn add(&mut self, path: &Path) {
+        let watcher = match &mut self.watcher {
+            Some(w) => w,
+            None => return,
+        };
+
+        let canonical = match canonical_path(path) {
+            Some(p) => p,
+            None => return,
+        };
+
+        // Watch the parent directory
+        if let Some(dir) = canonical.parent() {
+            if !self.dirs.contains(dir) {
+                let watched = watcher
+                    .watch(dir, RecursiveMode::NonRecursive)
+                    .map_err(|e| format!("Failed to watch `{}`: {}", dir.display(), e))
+                    .print_err()
+                    .is_ok();
+                if watched {
+                    self.dirs.insert(dir.to_path_buf());
+                }
+            }
+        }
+
+        self.files.insert(canonical, path.to_path_buf());
+    }
+
+    /// Stop watching the file at `path`
+    /// Its directory remains watched
+    pub fn remove(&mut self, path: &Path) {
+        self.files.retain(|_, list_path| list_path != path);
+    }
+
+    /// Interpret an event into changes to the image list's files
+    pub fn changes(&self, event: Event) -> Vec<FileChange> {
+        use EventKind::*;
+
+        // Events that leave a file in a complete state
+        let written = matches!(
+            event.kind,
+            Access(AccessKind::Close(AccessMode::Write))
+                | Create(_)
+                | Modify(ModifyKind::Name(RenameMode::To))
+        );
+        // Events that create new files
+        let created = matches!(
+            event.kind,
+            Create(_) | Modify(ModifyKind::Name(RenameMode::To))
+        );
+        // Events that remove files
+        let removed = matches!(
+            event.kind,
+            Remove(_) | Modify(ModifyKind::Name(RenameMode::From))
+        );
+
+        event
+            .paths
+            .into_iter()
+            .filter_map(|path| {
+                if let Some(list_path) = self.files.get(&path) {
+                    let kind = if written {
+                        FileChangeKind::Modify
+                    } else if removed {
+                        FileChangeKind::Remove
+                    } else {
+                        return None;
+                    };
+
+                    Some(FileChange {
+                        kind,
+                        path: list_path.clone(),
+                    })
+                } else if created && self.track_directories && is_image_path(&path) {
+                    Some(FileChange {
+                        kind: FileChangeKind::Create,
+                        path,
+                    })
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
+/// Get the canonical form of a path to a file that may not exist
+/// Only the parent directory is required to exist
+fn canonical_path(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?;
+
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+
+    let mut canonical = parent.canonicalize().ok()?;
+    canonical.push(file_name);
+
+    Some(canonical)
+}
+
+/// Check if the path looks like an image from its extension
+fn is_image_path(path: &Path) -> bool {
+    path.is_file() && ImageFormat::from_path(path).is_ok()
+}